@@ -22,6 +22,12 @@ CREATE TABLE orders (
 );
 """
 
+# Let the first generated token overlap the last prompt token
+HEAL_PROMPT: bool = False
+
+# The model may talk freely before and after its ```sql block
+SQL_TRIGGER: str = "```sql\n"
+
 def main() -> None:
     model_path: str = "./models/gemma-3-4b-it-Q8_0.gguf"
     vocabulary_path: str = "./vocabulary.tiktoken"
@@ -67,12 +73,17 @@ def main() -> None:
 
     vocab_size: int = model.n_vocab()
 
-    processor: LogitsProcessor = LogitsProcessor(vocab_size)
+    # EOS is only allowed once the statement is complete
+    session: oraculum.Session = oraculum.Session.from_sql(trigger=SQL_TRIGGER, eos_token_id=model.token_eos())
+    processor: LogitsProcessor = LogitsProcessor(vocab_size, session)
     logits_processor: LogitsProcessorList = LogitsProcessorList([processor])
 
     prompt: str = "Hello, how are you?"
     prompt_tokens: list[int] = model.tokenize(prompt.encode('utf-8'))
 
+    if HEAL_PROMPT:
+        prompt_tokens = session.heal(prompt_tokens)
+
     print("Generating response...\n")
 
     for token_id in model.generate(
@@ -81,12 +92,14 @@ def main() -> None:
         temp=0.7,
         logits_processor=logits_processor
     ):
+        if token_id == model.token_eos():
+            break
+
         processor.feed_token(token_id)
 
         text: str = model.detokenize([token_id]).decode('utf-8', errors='ignore')
 
-        # Disabled for now as the engine prints everything to stdout
-        # print(text, end='', flush=True)
+        print(text, end='', flush=True)
 
 if __name__ == "__main__":
     main()
@@ -3,15 +3,16 @@ import numpy as np
 import oraculum
 
 class LogitsProcessor:
-    def __init__(self, vocab_size: int) -> None:
+    def __init__(self, vocab_size: int, session: oraculum.Session) -> None:
         self._mask: NDArray[np.single] = np.full(vocab_size, -np.inf, dtype=np.float32)
+        self._session: oraculum.Session = session
 
     def __call__(
         self,
         input_ids: NDArray[np.intc],
         scores: NDArray[np.single]
     ) -> NDArray[np.single]:
-        routes: NDArray[np.uint32] = oraculum.routes()
+        routes: NDArray[np.uint32] = self._session.routes()
 
         self._mask.fill(-np.inf)
         self._mask[routes] = 0.0
@@ -19,4 +20,4 @@ class LogitsProcessor:
         return scores + self._mask
 
     def feed_token(self, token_id: int) -> int:
-        return oraculum.feed(token_id)
+        return self._session.feed(token_id)
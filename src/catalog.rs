@@ -0,0 +1,449 @@
+//! Tables and columns declared by the `CREATE TABLE` statements of a schema.
+
+/// Kind of value a column prefers, from its declared type by SQLite's rules.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Affinity {
+    Integer,
+    Real,
+    Numeric,
+    Text,
+    Blob,
+}
+
+impl Affinity {
+    pub fn from_type(declared: &str) -> Self {
+        let declared = declared.to_ascii_uppercase();
+        let has = |part: &str| declared.contains(part);
+
+        if has("INT") {
+            Self::Integer
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            Self::Text
+        } else if has("BLOB") {
+            Self::Blob
+        } else if has("REAL") || has("FLOA") || has("DOUB") {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+
+    pub fn is_number(self) -> bool {
+        matches!(self, Self::Integer | Self::Real | Self::Numeric)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Integer => "INTEGER",
+            Self::Real => "REAL",
+            Self::Numeric => "NUMERIC",
+            Self::Text => "TEXT",
+            Self::Blob => "BLOB",
+        }
+    }
+}
+
+/// `affinity` is `None` for columns declared without a type, which take any
+/// value.
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    pub affinity: Option<Affinity>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+impl Table {
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Catalog {
+    pub tables: Vec<Table>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Other(char),
+}
+
+fn tokenize(ddl: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = ddl.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            c if c.is_whitespace() => {}
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while chars.get(i + 1).is_some_and(|c| *c != '\n') {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+
+                while !(chars.get(i) == Some(&'*') && chars.get(i + 1) == Some(&'/')) {
+                    if i >= chars.len() {
+                        return Err("Unterminated comment in schema".to_string());
+                    }
+
+                    i += 1;
+                }
+
+                i += 1;
+            }
+            open @ ('"' | '`' | '[' | '\'') => {
+                let close = if open == '[' { ']' } else { open };
+                let mut text = String::new();
+
+                loop {
+                    i += 1;
+
+                    match chars.get(i) {
+                        None => return Err(format!("Unterminated `{}` in schema", open)),
+                        // Doubled quotes stand for themselves
+                        Some(&c) if c == close && open != '[' && chars.get(i + 1) == Some(&close) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                        Some(&c) if c == close => break,
+                        Some(&c) => text.push(c),
+                    }
+                }
+
+                // String literals only appear in defaults and checks, which
+                // are skipped
+                tokens.push(if open == '\'' { Token::Other('\'') } else { Token::Quoted(text) });
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let start = i;
+
+                while chars.get(i + 1).is_some_and(|c| c.is_alphanumeric() || *c == '_' || *c == '$') {
+                    i += 1;
+                }
+
+                tokens.push(Token::Word(chars[start..=i].iter().collect()));
+            }
+            other => tokens.push(Token::Other(other)),
+        }
+
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Keywords that start a table constraint instead of a column.
+const TABLE_CONSTRAINTS: &[&str] = &["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+/// Keywords that end the declared type of a column.
+const COLUMN_CONSTRAINTS: &[&str] = &[
+    "CONSTRAINT", "PRIMARY", "NOT", "NULL", "UNIQUE", "CHECK", "DEFAULT", "COLLATE", "REFERENCES", "GENERATED", "AS",
+];
+
+struct Reader {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+
+        if found {
+            self.pos += 1;
+        }
+
+        found
+    }
+
+    fn name(&mut self, what: &str) -> Result<String, String> {
+        let name = match self.peek() {
+            Some(Token::Word(name) | Token::Quoted(name)) => name.clone(),
+            _ => return Err(format!("Expected a {} name in schema", what)),
+        };
+
+        self.pos += 1;
+
+        Ok(name)
+    }
+
+    /// Skips to the next `,` or `)` outside parentheses, or the end of the
+    /// statement.
+    fn skip(&mut self) {
+        let mut depth = 0;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Other(',' | ')' | ';') if depth == 0 => return,
+                Token::Other('(') => depth += 1,
+                Token::Other(')') => depth -= 1,
+                _ => {}
+            }
+
+            self.pos += 1;
+        }
+    }
+
+    fn column(&mut self) -> Result<Column, String> {
+        let name = self.name("column")?;
+        let mut declared: Vec<&str> = Vec::new();
+
+        while let Some(Token::Word(word)) = self.tokens.get(self.pos + declared.len()) {
+            if COLUMN_CONSTRAINTS.iter().any(|keyword| word.eq_ignore_ascii_case(keyword)) {
+                break;
+            }
+
+            declared.push(word);
+        }
+
+        let affinity = (!declared.is_empty()).then(|| Affinity::from_type(&declared.join(" ")));
+
+        self.skip();
+
+        Ok(Column { name, affinity })
+    }
+
+    fn table(&mut self) -> Result<Table, String> {
+        self.keyword("IF");
+        self.keyword("NOT");
+        self.keyword("EXISTS");
+
+        let mut name = self.name("table")?;
+
+        // Only the table name of `schema.table` matters
+        if self.peek() == Some(&Token::Other('.')) {
+            self.pos += 1;
+            name = self.name("table")?;
+        }
+
+        if self.peek() != Some(&Token::Other('(')) {
+            return Err(format!("Table `{}` needs a column list", name));
+        }
+
+        let mut columns: Vec<Column> = Vec::new();
+
+        loop {
+            self.pos += 1;
+
+            let constraint = matches!(
+                self.peek(),
+                Some(Token::Word(word)) if TABLE_CONSTRAINTS.iter().any(|keyword| word.eq_ignore_ascii_case(keyword))
+            );
+
+            if constraint {
+                self.skip();
+            } else {
+                let column = self.column()?;
+
+                if columns.iter().any(|other| other.name.eq_ignore_ascii_case(&column.name)) {
+                    return Err(format!("Duplicate column `{}.{}`", name, column.name));
+                }
+
+                columns.push(column);
+            }
+
+            match self.peek() {
+                Some(Token::Other(',')) => {}
+                Some(Token::Other(')')) => break,
+                _ => return Err(format!("Unterminated column list of `{}`", name)),
+            }
+        }
+
+        Ok(Table { name, columns })
+    }
+}
+
+impl Catalog {
+    /// Reads the tables of the `CREATE TABLE` statements in `ddl`; other
+    /// statements are skipped.
+    pub fn from_ddl(ddl: &str) -> Result<Self, String> {
+        let mut reader = Reader { tokens: tokenize(ddl)?, pos: 0 };
+        let mut tables: Vec<Table> = Vec::new();
+
+        while reader.peek().is_some() {
+            if reader.keyword("CREATE") {
+                let _ = reader.keyword("TEMP") || reader.keyword("TEMPORARY");
+
+                if reader.keyword("TABLE") {
+                    let table = reader.table()?;
+
+                    if tables.iter().any(|other| other.name.eq_ignore_ascii_case(&table.name)) {
+                        return Err(format!("Duplicate table `{}`", table.name));
+                    }
+
+                    tables.push(table);
+                }
+            }
+
+            while reader.peek().is_some_and(|token| *token != Token::Other(';')) {
+                reader.pos += 1;
+            }
+
+            reader.pos += 1;
+        }
+
+        if tables.is_empty() {
+            return Err("Schema has no `CREATE TABLE` statements".to_string());
+        }
+
+        Ok(Self { tables })
+    }
+
+    /// The catalog without the tables and `table.column`s named in `hidden`.
+    pub fn without(&self, hidden: &[String]) -> Result<Self, String> {
+        let mut catalog = self.clone();
+
+        for name in hidden {
+            match name.split_once('.') {
+                Some((table, column)) => {
+                    let Some(table) = catalog.tables.iter_mut().find(|t| t.name.eq_ignore_ascii_case(table)) else {
+                        return Err(format!("Unknown table `{}`", table));
+                    };
+
+                    let Some(idx) = table.columns.iter().position(|c| c.name.eq_ignore_ascii_case(column)) else {
+                        return Err(format!("Unknown column `{}`", name));
+                    };
+
+                    table.columns.remove(idx);
+                }
+                None => {
+                    let Some(idx) = catalog.tables.iter().position(|t| t.name.eq_ignore_ascii_case(name)) else {
+                        return Err(format!("Unknown table `{}`", name));
+                    };
+
+                    catalog.tables.remove(idx);
+                }
+            }
+        }
+
+        Ok(catalog)
+    }
+
+    /// Table and column names of this catalog that `visible` no longer has
+    /// under any table.
+    pub fn hidden_names(&self, visible: &Catalog) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let known = |name: &str| {
+            visible.tables.iter().any(|table| table.name.eq_ignore_ascii_case(name) || table.column(name).is_some())
+        };
+
+        for table in &self.tables {
+            let columns = table.columns.iter().map(|column| &column.name);
+
+            for name in std::iter::once(&table.name).chain(columns) {
+                if !known(name) && !names.iter().any(|other| other.eq_ignore_ascii_case(name)) {
+                    names.push(name.clone());
+                }
+            }
+        }
+
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"
+        -- Accounts
+        CREATE TABLE users (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            email TEXT UNIQUE NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX users_email ON users (email);
+
+        /* Orders, with a composite key */
+        CREATE TABLE IF NOT EXISTS main."orders" (
+            id INTEGER,
+            user_id INTEGER REFERENCES users(id),
+            total DECIMAL(10, 2),
+            status VARCHAR(16) DEFAULT 'pending, (shipped)',
+            [note text] CHECK (length("note text") < 100),
+            PRIMARY KEY (id, user_id)
+        );
+    "#;
+
+    fn names(catalog: &Catalog) -> Vec<String> {
+        catalog.tables
+            .iter()
+            .flat_map(|table| table.columns.iter().map(move |column| format!("{}.{}", table.name, column.name)))
+            .collect()
+    }
+
+    #[test]
+    fn tables_and_columns() {
+        let catalog = Catalog::from_ddl(SCHEMA).unwrap();
+
+        assert_eq!(
+            names(&catalog),
+            [
+                "users.id", "users.name", "users.email", "users.created_at", "orders.id", "orders.user_id",
+                "orders.total", "orders.status", "orders.note text",
+            ],
+        );
+    }
+
+    #[test]
+    fn column_affinities() {
+        let catalog = Catalog::from_ddl(SCHEMA).unwrap();
+        let affinities: Vec<_> = catalog.tables.iter().flat_map(|table| &table.columns).map(|column| column.affinity).collect();
+
+        assert_eq!(
+            affinities,
+            [
+                Some(Affinity::Integer), Some(Affinity::Text), Some(Affinity::Text), Some(Affinity::Numeric),
+                Some(Affinity::Integer), Some(Affinity::Integer), Some(Affinity::Numeric), Some(Affinity::Text), None,
+            ],
+        );
+
+        let catalog = Catalog::from_ddl("CREATE TABLE t (a, b DOUBLE PRECISION, c BLOB NOT NULL, d FLOATING POINT);").unwrap();
+        let affinities: Vec<_> = catalog.tables[0].columns.iter().map(|column| column.affinity).collect();
+
+        // `POINT` has `INT` in it
+        assert_eq!(affinities, [None, Some(Affinity::Real), Some(Affinity::Blob), Some(Affinity::Integer)]);
+    }
+
+    #[test]
+    fn hidden_tables_and_columns() {
+        let catalog = Catalog::from_ddl(SCHEMA).unwrap();
+        let visible = catalog.without(&["users.email".to_string(), "orders".to_string()]).unwrap();
+
+        assert_eq!(names(&visible), ["users.id", "users.name", "users.created_at"]);
+        assert_eq!(catalog.hidden_names(&visible), ["email", "orders", "user_id", "total", "status", "note text"]);
+
+        // A name stays visible while another table still has it
+        let visible = catalog.without(&["orders.id".to_string()]).unwrap();
+
+        assert!(catalog.hidden_names(&visible).is_empty());
+
+        assert_eq!(catalog.without(&["accounts".to_string()]).unwrap_err(), "Unknown table `accounts`");
+        assert_eq!(catalog.without(&["users.phone".to_string()]).unwrap_err(), "Unknown column `users.phone`");
+    }
+
+    #[test]
+    fn schema_errors() {
+        assert_eq!(Catalog::from_ddl("CREATE INDEX i ON t (a);").unwrap_err(), "Schema has no `CREATE TABLE` statements");
+        assert_eq!(Catalog::from_ddl("CREATE TABLE t (a, A);").unwrap_err(), "Duplicate column `t.A`");
+        assert_eq!(Catalog::from_ddl("CREATE TABLE t (a); CREATE TABLE T (b);").unwrap_err(), "Duplicate table `T`");
+        assert_eq!(Catalog::from_ddl("CREATE TABLE t AS SELECT 1;").unwrap_err(), "Table `t` needs a column list");
+        assert_eq!(Catalog::from_ddl("CREATE TABLE t (a").unwrap_err(), "Unterminated column list of `t`");
+    }
+}
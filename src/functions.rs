@@ -0,0 +1,194 @@
+//! SQL functions a query may call, with the arguments they take.
+
+use crate::catalog::Affinity;
+
+use Arg::{Any, Number, Text, Time};
+
+/// Kind of value an argument has to be.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arg {
+    Any,
+    Number,
+    Text,
+    /// A date and time: text, or a number of days or seconds.
+    Time,
+}
+
+impl Arg {
+    /// Whether a value of `affinity` fits.
+    pub fn accepts(self, affinity: Affinity) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Number => affinity.is_number(),
+            Self::Text => affinity == Affinity::Text,
+            Self::Time => affinity != Affinity::Blob,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Any => "any value",
+            Self::Number => "a number",
+            Self::Text => "text",
+            Self::Time => "a date or time",
+        }
+    }
+}
+
+/// Function that takes `args`, the last of which repeats if `variadic`, and
+/// needs at least `required` of them. `result` is `None` where it depends on
+/// the arguments. Aggregate functions may also run over a window; `window`
+/// functions only do.
+pub struct Function {
+    pub name: &'static str,
+    pub args: &'static [Arg],
+    pub required: usize,
+    pub variadic: bool,
+    pub result: Option<Affinity>,
+    pub aggregate: bool,
+    pub window: bool,
+}
+
+impl Function {
+    const fn new(name: &'static str, args: &'static [Arg], result: Option<Affinity>) -> Self {
+        Self { name, args, required: args.len(), variadic: false, result, aggregate: false, window: false }
+    }
+
+    const fn optional(self, required: usize) -> Self {
+        Self { required, ..self }
+    }
+
+    const fn variadic(self) -> Self {
+        Self { variadic: true, ..self }
+    }
+
+    const fn aggregate(self) -> Self {
+        Self { aggregate: true, ..self }
+    }
+
+    const fn window(self) -> Self {
+        Self { window: true, ..self }
+    }
+
+    /// Whether it takes `count` arguments.
+    pub fn takes(&self, count: usize) -> bool {
+        count >= self.required && (self.variadic || count <= self.args.len())
+    }
+
+    /// Kind of the argument at `idx`.
+    pub fn arg(&self, idx: usize) -> Arg {
+        match self.args.get(idx) {
+            Some(&arg) => arg,
+            None => self.args.last().copied().unwrap_or(Arg::Any),
+        }
+    }
+
+    /// How many arguments it takes, for error messages.
+    pub fn arity(&self) -> String {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+
+        match (self.variadic, self.required == self.args.len()) {
+            (true, _) => format!("at least {} argument{}", self.required, plural(self.required)),
+            (false, true) => format!("{} argument{}", self.required, plural(self.required)),
+            (false, false) => format!("{} to {} arguments", self.required, self.args.len()),
+        }
+    }
+}
+
+/// SQLite's core, aggregate, date and window functions. `MIN` and `MAX`
+/// aggregate with one argument and pick one of several otherwise.
+pub const SQLITE: &[Function] = &[
+    Function::new("avg", &[Number], Some(Affinity::Real)).aggregate(),
+    Function::new("count", &[Any], Some(Affinity::Integer)).optional(0).aggregate(),
+    Function::new("group_concat", &[Any, Text], Some(Affinity::Text)).optional(1).aggregate(),
+    Function::new("max", &[Any], None).aggregate(),
+    Function::new("min", &[Any], None).aggregate(),
+    Function::new("sum", &[Number], None).aggregate(),
+    Function::new("total", &[Number], Some(Affinity::Real)).aggregate(),
+    Function::new("abs", &[Number], None),
+    Function::new("coalesce", &[Any, Any], None).variadic(),
+    Function::new("ifnull", &[Any, Any], None),
+    Function::new("instr", &[Text, Text], Some(Affinity::Integer)),
+    Function::new("length", &[Any], Some(Affinity::Integer)),
+    Function::new("lower", &[Text], Some(Affinity::Text)),
+    Function::new("ltrim", &[Text, Text], Some(Affinity::Text)).optional(1),
+    Function::new("max", &[Any, Any], None).variadic(),
+    Function::new("min", &[Any, Any], None).variadic(),
+    Function::new("nullif", &[Any, Any], None),
+    Function::new("random", &[], Some(Affinity::Integer)),
+    Function::new("replace", &[Text, Text, Text], Some(Affinity::Text)),
+    Function::new("round", &[Number, Number], Some(Affinity::Real)).optional(1),
+    Function::new("rtrim", &[Text, Text], Some(Affinity::Text)).optional(1),
+    Function::new("substr", &[Text, Number, Number], Some(Affinity::Text)).optional(2),
+    Function::new("trim", &[Text, Text], Some(Affinity::Text)).optional(1),
+    Function::new("typeof", &[Any], Some(Affinity::Text)),
+    Function::new("upper", &[Text], Some(Affinity::Text)),
+    Function::new("date", &[Time, Text], Some(Affinity::Text)).optional(1).variadic(),
+    Function::new("datetime", &[Time, Text], Some(Affinity::Text)).optional(1).variadic(),
+    Function::new("julianday", &[Time, Text], Some(Affinity::Real)).optional(1).variadic(),
+    Function::new("strftime", &[Text, Time, Text], Some(Affinity::Text)).optional(2).variadic(),
+    Function::new("time", &[Time, Text], Some(Affinity::Text)).optional(1).variadic(),
+    Function::new("cume_dist", &[], Some(Affinity::Real)).window(),
+    Function::new("dense_rank", &[], Some(Affinity::Integer)).window(),
+    Function::new("first_value", &[Any], None).window(),
+    Function::new("lag", &[Any, Number, Any], None).optional(1).window(),
+    Function::new("last_value", &[Any], None).window(),
+    Function::new("lead", &[Any, Number, Any], None).optional(1).window(),
+    Function::new("nth_value", &[Any, Number], None).window(),
+    Function::new("ntile", &[Number], Some(Affinity::Integer)).window(),
+    Function::new("percent_rank", &[], Some(Affinity::Real)).window(),
+    Function::new("rank", &[], Some(Affinity::Integer)).window(),
+    Function::new("row_number", &[], Some(Affinity::Integer)).window(),
+];
+
+/// Names of the functions in `functions`, each once.
+pub fn names(functions: &[Function]) -> Vec<&'static str> {
+    let mut names: Vec<&str> = functions.iter().map(|function| function.name).collect();
+
+    names.sort_unstable();
+    names.dedup();
+
+    names
+}
+
+/// The function `name` that takes `count` arguments, or else any function
+/// called `name`.
+pub fn find<'a>(functions: &'a [Function], name: &str, count: usize) -> Option<&'a Function> {
+    let mut named = functions.iter().filter(|function| function.name.eq_ignore_ascii_case(name)).peekable();
+    let first = named.peek().copied();
+
+    named.find(|function| function.takes(count)).or(first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arities() {
+        let arities: Vec<(bool, String)> = ["lower", "substr", "coalesce", "count", "random"]
+            .into_iter()
+            .map(|name| {
+                let function = find(SQLITE, name, 0).unwrap();
+
+                (function.takes(2), function.arity())
+            })
+            .collect();
+
+        assert_eq!(
+            arities,
+            [
+                (false, "1 argument".to_string()),
+                (true, "2 to 3 arguments".to_string()),
+                (true, "at least 2 arguments".to_string()),
+                (false, "0 to 1 arguments".to_string()),
+                (false, "0 arguments".to_string()),
+            ],
+        );
+
+        assert!(find(SQLITE, "MAX", 1).unwrap().aggregate);
+        assert!(!find(SQLITE, "MAX", 3).unwrap().aggregate);
+        assert!(find(SQLITE, "nope", 1).is_none());
+        assert_eq!(find(SQLITE, "strftime", 4).unwrap().arg(3), Arg::Text);
+    }
+}
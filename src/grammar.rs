@@ -0,0 +1,833 @@
+use std::collections::HashMap;
+
+use derivre::RegexAst;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    Terminal(u32),
+    Nonterminal(u32),
+}
+
+pub struct Rule {
+    pub lhs: u32,
+    pub rhs: Vec<Symbol>,
+}
+
+/// Context-free grammar over lexemes. Every terminal is a regex; the optional
+/// `ignore` terminal may appear between any two lexemes. With
+/// `separate_words`, two lexemes can't meet between two word characters, so
+/// keywords and identifiers need something between them. With
+/// `single_ignore`, at most one `ignore` lexeme comes between two others, so
+/// its regex alone decides what may separate them.
+///
+/// `literals` holds the text of terminals that match one string (up to case
+/// for `"..."i`), `names` how each terminal was written in the grammar.
+pub struct Grammar {
+    pub terminals: Vec<RegexAst>,
+    pub literals: Vec<Option<String>>,
+    pub names: Vec<String>,
+    pub nonterminals: Vec<String>,
+    pub rules: Vec<Rule>,
+    pub start: u32,
+    pub ignore: Option<u32>,
+    pub separate_words: bool,
+    pub single_ignore: bool,
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Literal(String, bool),
+    Pattern(String, String),
+    Directive(String),
+    Colon,
+    Pipe,
+    Ampersand,
+    Tilde,
+    Arrow,
+    Open,
+    Close,
+    OpenOptional,
+    CloseOptional,
+    Operator(char),
+}
+
+#[derive(Clone)]
+enum Expr {
+    Alternatives(Vec<Expr>),
+    Intersection(Vec<Expr>),
+    Complement(Box<Expr>),
+    Sequence(Vec<Expr>),
+    Literal(String, bool),
+    Pattern(String, String),
+    Name(String),
+    Optional(Box<Expr>),
+    Star(Box<Expr>),
+    Plus(Box<Expr>),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line_start = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '\n' {
+            line_start = true;
+            i += 1;
+
+            continue;
+        }
+
+        if c.is_whitespace() {
+            i += 1;
+
+            continue;
+        }
+
+        if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+
+            continue;
+        }
+
+        // `?rule` and `!rule` only change the shape of Lark's parse tree
+        if line_start && (c == '?' || c == '!') {
+            i += 1;
+
+            continue;
+        }
+
+        line_start = false;
+
+        match c {
+            ':' => tokens.push(Token::Colon),
+            '|' => tokens.push(Token::Pipe),
+            '&' => tokens.push(Token::Ampersand),
+            '~' => tokens.push(Token::Tilde),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '[' => tokens.push(Token::OpenOptional),
+            ']' => tokens.push(Token::CloseOptional),
+            '?' | '*' | '+' => tokens.push(Token::Operator(c)),
+            '-' if chars.get(i + 1) == Some(&'>') => {
+                tokens.push(Token::Arrow);
+                i += 1;
+            }
+            '"' => {
+                let mut value = String::new();
+
+                i += 1;
+
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') => return Err("Unterminated string literal".to_string()),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.get(i + 1) {
+                                Some('n') => '\n',
+                                Some('t') => '\t',
+                                Some('r') => '\r',
+                                Some(&other) => other,
+                                None => return Err("Unterminated string literal".to_string()),
+                            };
+
+                            value.push(escaped);
+                            i += 2;
+                        }
+                        Some(&other) => {
+                            value.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+
+                let insensitive = chars.get(i + 1) == Some(&'i');
+
+                if insensitive {
+                    i += 1;
+                }
+
+                tokens.push(Token::Literal(value, insensitive));
+            }
+            '/' => {
+                let mut pattern = String::new();
+
+                i += 1;
+
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') => return Err("Unterminated regex".to_string()),
+                        Some('/') => break,
+                        Some('\\') if chars.get(i + 1) == Some(&'/') => {
+                            pattern.push('/');
+                            i += 2;
+                        }
+                        Some('\\') => {
+                            pattern.push('\\');
+                            pattern.extend(chars.get(i + 1));
+                            i += 2;
+                        }
+                        Some(&other) => {
+                            pattern.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+
+                let mut flags = String::new();
+
+                while let Some(&flag) = chars.get(i + 1).filter(|f| "imsx".contains(**f)) {
+                    flags.push(flag);
+                    i += 1;
+                }
+
+                tokens.push(Token::Pattern(pattern, flags));
+            }
+            '%' => {
+                let start = i + 1;
+
+                while chars.get(i + 1).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+                    i += 1;
+                }
+
+                let directive: String = chars[start..=i].iter().collect();
+
+                // Only `%ignore` takes grammar syntax; skip the arguments of
+                // other directives (e.g. `%import common.WS`) so they are
+                // reported as unsupported instead of failing to tokenize
+                if directive != "ignore" {
+                    while chars.get(i + 1).is_some_and(|c| *c != '\n') {
+                        i += 1;
+                    }
+                }
+
+                tokens.push(Token::Directive(directive));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+
+                while chars.get(i + 1).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+                    i += 1;
+                }
+
+                tokens.push(Token::Name(chars[start..=i].iter().collect()));
+
+                // Priorities (`rule.2:`) are irrelevant for constraining
+                if chars.get(i + 1) == Some(&'.') && chars.get(i + 2).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1;
+
+                    while chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                        i += 1;
+                    }
+                }
+            }
+            other => return Err(format!("Unexpected character `{}`", other)),
+        }
+
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+struct Reader {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn at_definition(&self) -> bool {
+        matches!(self.peek(), Some(Token::Name(_))) && self.tokens.get(self.pos + 1) == Some(&Token::Colon)
+    }
+
+    fn alternatives(&mut self) -> Result<Expr, String> {
+        let mut alternatives = vec![self.intersection()?];
+
+        while self.peek() == Some(&Token::Pipe) {
+            self.pos += 1;
+
+            alternatives.push(self.intersection()?);
+        }
+
+        Ok(Expr::Alternatives(alternatives))
+    }
+
+    fn intersection(&mut self) -> Result<Expr, String> {
+        let sequence = self.sequence()?;
+
+        if self.peek() != Some(&Token::Ampersand) {
+            return Ok(sequence);
+        }
+
+        let mut operands = vec![sequence];
+
+        while self.peek() == Some(&Token::Ampersand) {
+            self.pos += 1;
+
+            operands.push(self.sequence()?);
+        }
+
+        Ok(Expr::Intersection(operands))
+    }
+
+    fn sequence(&mut self) -> Result<Expr, String> {
+        let mut items = Vec::new();
+        let mut complement = false;
+
+        loop {
+            if self.at_definition() {
+                break;
+            }
+
+            let atom = match self.peek().cloned() {
+                Some(Token::Tilde) => {
+                    self.pos += 1;
+
+                    complement = true;
+
+                    continue;
+                }
+                Some(Token::Name(name)) => {
+                    self.pos += 1;
+
+                    Expr::Name(name)
+                }
+                Some(Token::Literal(value, insensitive)) => {
+                    self.pos += 1;
+
+                    Expr::Literal(value, insensitive)
+                }
+                Some(Token::Pattern(pattern, flags)) => {
+                    self.pos += 1;
+
+                    Expr::Pattern(pattern, flags)
+                }
+                Some(Token::Open) => {
+                    self.pos += 1;
+
+                    let inner = self.alternatives()?;
+
+                    self.expect(Token::Close, ")")?;
+
+                    inner
+                }
+                Some(Token::OpenOptional) => {
+                    self.pos += 1;
+
+                    let inner = self.alternatives()?;
+
+                    self.expect(Token::CloseOptional, "]")?;
+
+                    Expr::Optional(Box::new(inner))
+                }
+                Some(Token::Arrow) => {
+                    // Aliases only rename tree nodes
+                    self.pos += 1;
+
+                    let Some(Token::Name(_)) = self.peek() else {
+                        return Err("Expected alias name after `->`".to_string());
+                    };
+
+                    self.pos += 1;
+
+                    continue;
+                }
+                _ => break,
+            };
+
+            let atom = match self.peek() {
+                Some(Token::Operator('?')) => Expr::Optional(Box::new(atom)),
+                Some(Token::Operator('*')) => Expr::Star(Box::new(atom)),
+                Some(Token::Operator('+')) => Expr::Plus(Box::new(atom)),
+                _ => atom,
+            };
+
+            if matches!(self.peek(), Some(Token::Operator(_))) {
+                self.pos += 1;
+            }
+
+            if complement {
+                items.push(Expr::Complement(Box::new(atom)));
+            } else {
+                items.push(atom);
+            }
+
+            complement = false;
+        }
+
+        if complement {
+            return Err("Expected an operand after `~`".to_string());
+        }
+
+        Ok(Expr::Sequence(items))
+    }
+
+    fn expect(&mut self, token: Token, text: &str) -> Result<(), String> {
+        if self.peek() != Some(&token) {
+            return Err(format!("Expected `{}`", text));
+        }
+
+        self.pos += 1;
+
+        Ok(())
+    }
+}
+
+fn is_terminal_name(name: &str) -> bool {
+    name.trim_start_matches('_').starts_with(|c: char| c.is_uppercase())
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn literal_regex(value: &str, insensitive: bool) -> RegexAst {
+    if insensitive {
+        RegexAst::Regex(format!("(?i:{})", escape(value)))
+    } else {
+        RegexAst::Literal(value.to_string())
+    }
+}
+
+fn pattern_regex(pattern: &str, flags: &str) -> RegexAst {
+    if flags.is_empty() {
+        RegexAst::Regex(pattern.to_string())
+    } else {
+        RegexAst::Regex(format!("(?{}:{})", flags, pattern))
+    }
+}
+
+/// Text of an expression that is a single string literal.
+fn literal_text(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Literal(value, _) => Some(value.clone()),
+        Expr::Sequence(items) | Expr::Alternatives(items) if items.len() == 1 => literal_text(&items[0]),
+        _ => None,
+    }
+}
+
+struct Builder {
+    definitions: HashMap<String, Expr>,
+    terminals: Vec<RegexAst>,
+    literals: Vec<Option<String>>,
+    names: Vec<String>,
+    terminal_ids: HashMap<String, u32>,
+    nonterminals: Vec<String>,
+    nonterminal_ids: HashMap<String, u32>,
+    rules: Vec<Rule>,
+}
+
+impl Builder {
+    fn terminal_regexes(&self, exprs: &[Expr], visiting: &mut Vec<String>) -> Result<Vec<RegexAst>, String> {
+        exprs.iter().map(|expr| self.terminal_regex(expr, visiting)).collect()
+    }
+
+    fn terminal_regex(&self, expr: &Expr, visiting: &mut Vec<String>) -> Result<RegexAst, String> {
+        let regex = match expr {
+            Expr::Alternatives(alternatives) => RegexAst::Or(self.terminal_regexes(alternatives, visiting)?),
+            Expr::Intersection(operands) => RegexAst::And(self.terminal_regexes(operands, visiting)?),
+            Expr::Complement(inner) => RegexAst::Not(Box::new(self.terminal_regex(inner, visiting)?)),
+            Expr::Sequence(items) => RegexAst::Concat(self.terminal_regexes(items, visiting)?),
+            Expr::Literal(value, insensitive) => literal_regex(value, *insensitive),
+            Expr::Pattern(pattern, flags) => pattern_regex(pattern, flags),
+            Expr::Optional(inner) => RegexAst::Repeat(Box::new(self.terminal_regex(inner, visiting)?), 0, 1),
+            Expr::Star(inner) => RegexAst::Repeat(Box::new(self.terminal_regex(inner, visiting)?), 0, u32::MAX),
+            Expr::Plus(inner) => RegexAst::Repeat(Box::new(self.terminal_regex(inner, visiting)?), 1, u32::MAX),
+            Expr::Name(name) => {
+                if !is_terminal_name(name) {
+                    return Err(format!("Terminal cannot reference rule `{}`", name));
+                }
+
+                if visiting.contains(name) {
+                    return Err(format!("Terminal `{}` is recursive", name));
+                }
+
+                let Some(definition) = self.definitions.get(name) else {
+                    return Err(format!("Undefined terminal `{}`", name));
+                };
+
+                visiting.push(name.clone());
+
+                let regex = self.terminal_regex(definition, visiting)?;
+
+                visiting.pop();
+
+                regex
+            }
+        };
+
+        Ok(regex)
+    }
+
+    /// Interns the terminal `key`, whose definition is `expr` if it has one.
+    fn terminal(
+        &mut self,
+        key: String,
+        expr: Option<Expr>,
+        regex: impl FnOnce(&Self) -> Result<RegexAst, String>,
+    ) -> Result<u32, String> {
+        if let Some(&id) = self.terminal_ids.get(&key) {
+            return Ok(id);
+        }
+
+        let id = self.terminals.len() as u32;
+        let literal = expr.as_ref().and_then(literal_text);
+
+        self.terminals.push(regex(self)?);
+        self.literals.push(literal);
+        self.names.push(key.clone());
+        self.terminal_ids.insert(key, id);
+
+        Ok(id)
+    }
+
+    fn nonterminal(&mut self, name: &str) -> u32 {
+        if let Some(&id) = self.nonterminal_ids.get(name) {
+            return id;
+        }
+
+        let id = self.nonterminals.len() as u32;
+
+        self.nonterminals.push(name.to_string());
+        self.nonterminal_ids.insert(name.to_string(), id);
+
+        id
+    }
+
+    fn fresh(&mut self, parent: u32) -> u32 {
+        let name = format!("{}#{}", self.nonterminals[parent as usize], self.nonterminals.len());
+
+        self.nonterminal(&name)
+    }
+
+    fn symbol(&mut self, expr: &Expr, parent: u32) -> Result<Symbol, String> {
+        let symbol = match expr {
+            Expr::Literal(value, insensitive) => {
+                let key = format!("\"{}\"{}", value, if *insensitive { "i" } else { "" });
+
+                Symbol::Terminal(self.terminal(key, Some(expr.clone()), |_| Ok(literal_regex(value, *insensitive)))?)
+            }
+            Expr::Pattern(pattern, flags) => {
+                let key = format!("/{}/{}", pattern, flags);
+
+                Symbol::Terminal(self.terminal(key, None, |_| Ok(pattern_regex(pattern, flags)))?)
+            }
+            Expr::Intersection(_) | Expr::Complement(_) => {
+                // Anonymous terminal, like an inline pattern
+                let key = format!("%anonymous{}", self.terminals.len());
+
+                Symbol::Terminal(self.terminal(key, None, |builder| builder.terminal_regex(expr, &mut Vec::new()))?)
+            }
+            Expr::Name(name) if is_terminal_name(name) => {
+                let definition = self.definitions.get(name).cloned();
+                let id = self.terminal(name.clone(), definition, |builder| {
+                    builder.terminal_regex(expr, &mut Vec::new())
+                })?;
+
+                Symbol::Terminal(id)
+            }
+            Expr::Name(name) => {
+                if !self.definitions.contains_key(name) {
+                    return Err(format!("Undefined rule `{}`", name));
+                }
+
+                Symbol::Nonterminal(self.nonterminal(name))
+            }
+            Expr::Sequence(items) if items.len() == 1 => self.symbol(&items[0], parent)?,
+            Expr::Alternatives(alternatives) if alternatives.len() == 1 => self.symbol(&alternatives[0], parent)?,
+            Expr::Optional(inner) => {
+                let id = self.fresh(parent);
+
+                self.rules.push(Rule { lhs: id, rhs: Vec::new() });
+                self.alternatives(id, inner)?;
+
+                Symbol::Nonterminal(id)
+            }
+            Expr::Star(inner) | Expr::Plus(inner) => {
+                let id = self.fresh(parent);
+                let item = self.symbol(inner, id)?;
+
+                if matches!(expr, Expr::Star(_)) {
+                    self.rules.push(Rule { lhs: id, rhs: Vec::new() });
+                } else {
+                    self.rules.push(Rule { lhs: id, rhs: vec![item] });
+                }
+
+                self.rules.push(Rule { lhs: id, rhs: vec![Symbol::Nonterminal(id), item] });
+
+                Symbol::Nonterminal(id)
+            }
+            Expr::Sequence(_) | Expr::Alternatives(_) => {
+                let id = self.fresh(parent);
+
+                self.alternatives(id, expr)?;
+
+                Symbol::Nonterminal(id)
+            }
+        };
+
+        Ok(symbol)
+    }
+
+    fn alternatives(&mut self, lhs: u32, expr: &Expr) -> Result<(), String> {
+        let alternatives = match expr {
+            Expr::Alternatives(alternatives) => alternatives.clone(),
+            other => vec![other.clone()],
+        };
+
+        for alternative in alternatives {
+            let items = match alternative {
+                Expr::Sequence(items) => items,
+                other => vec![other],
+            };
+
+            let rhs = items
+                .iter()
+                .map(|item| self.symbol(item, lhs))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            self.rules.push(Rule { lhs, rhs });
+        }
+
+        Ok(())
+    }
+}
+
+impl Grammar {
+    /// Grammar whose only rule is a single lexeme matching `pattern`.
+    pub fn from_regex(pattern: &str) -> Self {
+        Self {
+            terminals: vec![RegexAst::Regex(pattern.to_string())],
+            literals: vec![None],
+            names: vec![format!("/{}/", pattern)],
+            nonterminals: vec!["start".to_string()],
+            rules: vec![Rule { lhs: 0, rhs: vec![Symbol::Terminal(0)] }],
+            start: 0,
+            ignore: None,
+            separate_words: false,
+            single_ignore: false,
+        }
+    }
+
+    /// Lets the input end with one of `terminators` after a complete
+    /// sentence; nothing follows a terminator.
+    pub fn terminated_by(mut self, terminators: &[String]) -> Self {
+        if terminators.is_empty() {
+            return self;
+        }
+
+        let start = self.nonterminals.len() as u32;
+
+        self.nonterminals.push("%start".to_string());
+        self.rules.push(Rule { lhs: start, rhs: vec![Symbol::Nonterminal(self.start)] });
+
+        for terminator in terminators {
+            let id = self.terminals.len() as u32;
+
+            self.terminals.push(RegexAst::Literal(terminator.clone()));
+            self.literals.push(Some(terminator.clone()));
+            self.names.push(format!("{:?}", terminator));
+            self.rules.push(Rule { lhs: start, rhs: vec![Symbol::Nonterminal(self.start), Symbol::Terminal(id)] });
+        }
+
+        self.start = start;
+        self
+    }
+
+    /// Parses a grammar in a subset of Lark's EBNF: rules, terminals, string
+    /// literals (with the `i` flag), `/regex/` patterns, grouping, `?`, `*`,
+    /// `+`, `[...]` and `%ignore`. The entry point is the `start` rule.
+    ///
+    /// `%separate_words` and `%single_ignore` turn on the flags of the same
+    /// name.
+    ///
+    /// Terminals may also use `A & B` (both match) and `~A` (anything `A`
+    /// doesn't match), e.g. to keep identifiers from matching keywords.
+    pub fn from_lark(text: &str) -> Result<Self, String> {
+        let mut reader = Reader { tokens: tokenize(text)?, pos: 0 };
+        let mut definitions = HashMap::new();
+        let mut order = Vec::new();
+        let mut ignores = Vec::new();
+        let mut separate_words = false;
+        let mut single_ignore = false;
+
+        while let Some(token) = reader.peek().cloned() {
+            match token {
+                Token::Directive(directive) if directive == "ignore" => {
+                    reader.pos += 1;
+
+                    ignores.push(reader.alternatives()?);
+                }
+                Token::Directive(directive) if directive == "separate_words" => {
+                    reader.pos += 1;
+                    separate_words = true;
+                }
+                Token::Directive(directive) if directive == "single_ignore" => {
+                    reader.pos += 1;
+                    single_ignore = true;
+                }
+                Token::Directive(directive) => {
+                    return Err(format!("Unsupported directive `%{}`", directive));
+                }
+                Token::Name(name) if reader.at_definition() => {
+                    reader.pos += 2;
+
+                    let expr = reader.alternatives()?;
+
+                    if definitions.insert(name.clone(), expr).is_some() {
+                        return Err(format!("Duplicate definition of `{}`", name));
+                    }
+
+                    order.push(name);
+                }
+                _ => return Err("Expected a rule or terminal definition".to_string()),
+            }
+        }
+
+        if !definitions.contains_key("start") {
+            return Err("Missing `start` rule".to_string());
+        }
+
+        let mut builder = Builder {
+            definitions,
+            terminals: Vec::new(),
+            literals: Vec::new(),
+            names: Vec::new(),
+            terminal_ids: HashMap::new(),
+            nonterminals: Vec::new(),
+            nonterminal_ids: HashMap::new(),
+            rules: Vec::new(),
+        };
+
+        let start = builder.nonterminal("start");
+
+        for name in order.iter().filter(|name| !is_terminal_name(name)) {
+            let lhs = builder.nonterminal(name);
+            let expr = builder.definitions[name].clone();
+
+            builder.alternatives(lhs, &expr)?;
+        }
+
+        let ignore = if ignores.is_empty() {
+            None
+        } else {
+            let ignore = Expr::Alternatives(ignores);
+            let id = builder.terminal("%ignore".to_string(), None, |builder| {
+                builder.terminal_regex(&ignore, &mut Vec::new())
+            })?;
+
+            Some(id)
+        };
+
+        Ok(Self {
+            terminals: builder.terminals,
+            literals: builder.literals,
+            names: builder.names,
+            nonterminals: builder.nonterminals,
+            rules: builder.rules,
+            start,
+            ignore,
+            separate_words,
+            single_ignore,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        match Grammar::from_lark(text) {
+            Ok(_) => panic!("grammar should not parse: {}", text),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error("rule: \"a\""), "Missing `start` rule");
+        assert_eq!(error("start: missing"), "Undefined rule `missing`");
+        assert_eq!(error("start: MISSING"), "Undefined terminal `MISSING`");
+        assert_eq!(error("start: A\nA: \"a\" A"), "Terminal `A` is recursive");
+        assert_eq!(error("start: A\nA: rule\nrule: \"a\""), "Terminal cannot reference rule `rule`");
+        assert_eq!(error("start: \"a\"\nstart: \"b\""), "Duplicate definition of `start`");
+        assert_eq!(error("start: \"a"), "Unterminated string literal");
+        assert_eq!(error("start: /a"), "Unterminated regex");
+        assert_eq!(error("start: (\"a\""), "Expected `)`");
+        assert_eq!(error("start: [\"a\""), "Expected `]`");
+        assert_eq!(error("start: \"a\" ;"), "Unexpected character `;`");
+        assert_eq!(error("%import common.WS\nstart: WS"), "Unsupported directive `%import`");
+        assert_eq!(error("start: \"a\"\n%declare FOO"), "Unsupported directive `%declare`");
+        assert_eq!(error("start: A\nA: /[a-z]+/ ~"), "Expected an operand after `~`");
+    }
+
+    fn matches(ast: &RegexAst, text: &str) -> bool {
+        let mut builder = derivre::RegexBuilder::new();
+        let expr = builder.mk(ast).unwrap();
+
+        builder.into_regex(expr).is_match(text)
+    }
+
+    #[test]
+    fn ebnf_expansion() {
+        let grammar = Grammar::from_lark(
+            "?start: item+ [\",\"] (\"a\" | \"b\")* -> alias\nitem.2: NAME?\nNAME: /[a-z]+/i\n%ignore \" \"",
+        )
+        .unwrap();
+
+        assert_eq!(grammar.nonterminals[grammar.start as usize], "start");
+        assert_eq!(grammar.terminals.len(), 5);
+        assert!(grammar.terminals.iter().any(|t| matches(t, "AbC")));
+        assert!(matches(&grammar.terminals[grammar.ignore.unwrap() as usize], " "));
+        assert!(grammar.rules.iter().any(|rule| rule.rhs.is_empty()));
+    }
+
+    #[test]
+    fn terminals_compose() {
+        let grammar = Grammar::from_lark("start: NUMBER\nNUMBER: DIGIT+ (\".\" DIGIT+)?\nDIGIT: /[0-9]/").unwrap();
+
+        assert_eq!(grammar.terminals.len(), 1);
+
+        for (text, expected) in [("12", true), ("1.5", true), ("1.", false), (".5", false), ("1x5", false)] {
+            assert_eq!(matches(&grammar.terminals[0], text), expected, "{}", text);
+        }
+    }
+
+    #[test]
+    fn terminal_operators() {
+        let grammar = Grammar::from_lark(
+            "start: NAME \"=\" ~/[0-9]+/\nNAME: /[a-z]+/ & ~KEYWORD\nKEYWORD: \"if\" | \"else\"",
+        )
+        .unwrap();
+
+        assert_eq!(grammar.terminals.len(), 3);
+
+        for (text, expected) in [("x", true), ("iff", true), ("if", false), ("else", false)] {
+            assert_eq!(matches(&grammar.terminals[0], text), expected, "{}", text);
+        }
+
+        assert!(matches(&grammar.terminals[2], "x1"));
+        assert!(!matches(&grammar.terminals[2], "12"));
+    }
+}
@@ -1,3 +1,13 @@
+mod catalog;
+mod functions;
+mod grammar;
+mod parser;
+mod query;
+mod schema;
+mod session;
+mod sql;
+mod trie;
+
 use std::collections::HashMap;
 use std::io::Write;
 use std::sync::OnceLock;
@@ -6,12 +16,17 @@ use base64::{Engine, engine::general_purpose::STANDARD};
 use numpy::PyArray1;
 use pyo3::prelude::*;
 
+use catalog::Catalog;
+use session::{Session, fill_masks};
+use trie::TokenTrie;
+
 struct Vocabulary {
     token_to_id: HashMap<&'static str, u32>,
     id_to_token: HashMap<u32, &'static str>,
     idx_to_id: HashMap<usize, u32>,
     tokens: Vec<&'static str>,
     token_ids: Vec<u32>,
+    trie: TokenTrie,
 }
 
 impl Vocabulary {
@@ -22,6 +37,7 @@ impl Vocabulary {
             idx_to_id: HashMap::new(),
             tokens: Vec::new(),
             token_ids: Vec::new(),
+            trie: TokenTrie::new([]),
         }
     }
 
@@ -51,40 +67,66 @@ impl Vocabulary {
             self.token_ids.push(id);
         }
 
+        let words = self.tokens.iter().map(|token| token.as_bytes());
+
+        self.trie = TokenTrie::new(words.zip(self.token_ids.iter().copied()));
+
         Ok(())
     }
 }
 
 static ENGINE: OnceLock<Vocabulary> = OnceLock::new();
+static SCHEMA: OnceLock<Catalog> = OnceLock::new();
 
 #[pyfunction]
-fn init_vocabulary(data: &[u8]) -> i32 {
+fn init_vocabulary(py: Python<'_>, data: &[u8]) -> i32 {
     if ENGINE.get().is_some() {
         return 1;
     }
 
-    let mut vocabulary = Vocabulary::new();
+    py.allow_threads(|| {
+        let mut vocabulary = Vocabulary::new();
 
-    if vocabulary.load(data).is_err() {
-        return 1;
-    }
+        if vocabulary.load(data).is_err() {
+            return 1;
+        }
 
-    println!("Loaded {} tokens", vocabulary.tokens.len());
+        println!("Loaded {} tokens", vocabulary.tokens.len());
 
-    if ENGINE.set(vocabulary).is_err() {
-        return 1;
-    }
+        if ENGINE.set(vocabulary).is_err() {
+            return 1;
+        }
 
-    0
+        0
+    })
 }
 
+/// Reads the tables SQL sessions may refer to from `CREATE TABLE`
+/// statements.
 #[pyfunction]
 fn init_schema(data: &[u8]) -> i32 {
+    if SCHEMA.get().is_some() {
+        return 1;
+    }
+
     let Ok(text) = std::str::from_utf8(data) else {
         return 1;
     };
 
-    println!("Schema:\n{}", text);
+    let catalog = match Catalog::from_ddl(text) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            println!("Invalid schema: {}", e);
+
+            return 1;
+        }
+    };
+
+    println!("Loaded {} tables", catalog.tables.len());
+
+    if SCHEMA.set(catalog).is_err() {
+        return 1;
+    }
 
     0
 }
@@ -95,24 +137,28 @@ fn routes<'py>(py: Python<'py>) -> Bound<'py, PyArray1<u32>> {
         return PyArray1::from_slice_bound(py, &[]);
     };
 
-    PyArray1::from_slice_bound(py, &vocab.token_ids)
+    let routes = py.allow_threads(|| vocab.token_ids.clone());
+
+    PyArray1::from_vec_bound(py, routes)
 }
 
 #[pyfunction]
-fn feed(token_id: u32) -> i32 {
+fn feed(py: Python<'_>, token_id: u32) -> i32 {
     let Some(vocabulary) = ENGINE.get() else {
         return 1;
     };
 
-    let Some(token) = vocabulary.id_to_token.get(&token_id) else {
-        return 1;
-    };
+    py.allow_threads(|| {
+        let Some(token) = vocabulary.id_to_token.get(&token_id) else {
+            return 1;
+        };
 
-    print!("{}", token);
+        print!("{}", token);
 
-    let _ = std::io::stdout().flush();
+        let _ = std::io::stdout().flush();
 
-    0
+        0
+    })
 }
 
 #[pymodule]
@@ -121,6 +167,8 @@ fn oraculum(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(init_schema, m)?)?;
     m.add_function(wrap_pyfunction!(routes, m)?)?;
     m.add_function(wrap_pyfunction!(feed, m)?)?;
+    m.add_function(wrap_pyfunction!(fill_masks, m)?)?;
+    m.add_class::<Session>()?;
 
     Ok(())
 }
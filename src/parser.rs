@@ -0,0 +1,1192 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use derivre::{Regex, RegexBuilder, StateID};
+
+use crate::grammar::{Grammar, Symbol};
+use crate::trie::TokenTrie;
+
+const DEAD: u32 = u32::MAX;
+
+/// Longest continuation `forced()` looks ahead for, in case the grammar
+/// forces an endless one.
+const MAX_FORCED: usize = 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    rule: u32,
+    dot: u32,
+    origin: u32,
+}
+
+/// Earley set after a completed lexeme, together with the lexer state that
+/// starts the next lexeme from it, the one that starts it after an ignored
+/// lexeme, the lexer state that was finished to reach it and the input offset
+/// where that lexeme ended.
+struct Row {
+    items: Vec<Item>,
+    lexer: u32,
+    resume: u32,
+    source: u32,
+    end: u32,
+}
+
+/// Position inside the input: the number of Earley rows built so far, the
+/// lexemes that are still alive for the bytes since the last row, whether
+/// the last byte was a word character and the number of bytes read.
+#[derive(Clone, Copy)]
+pub struct State {
+    rows: u32,
+    lexer: u32,
+    word: bool,
+    offset: u32,
+}
+
+/// Node of a parse tree: a rule with its children, or a lexeme with the name
+/// of its terminal (the text of literal terminals) and the text it matched.
+/// The helper rules that `[...]`, `(...)`, `*` and `+` expand to are spliced
+/// into their parents.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Rule(String, Vec<Node>),
+    Lexeme(String, String),
+}
+
+/// Earley parser over lexemes matched lazily, byte by byte, with derivre.
+/// Lexing is greedy: a lexeme only ends when no live lexeme can take the next
+/// byte.
+///
+/// Clones share the grammar and the rows parsed so far; only the lexer caches
+/// and the input are copied. The input holds the bytes up to the current
+/// state, followed by the ones the latest lookahead stepped over.
+#[derive(Clone)]
+pub struct Parser {
+    grammar: Arc<Grammar>,
+    regexes: Vec<Regex>,
+    initial: Vec<StateID>,
+    by_lhs: Arc<Vec<Vec<u32>>>,
+    nullable: Arc<Vec<bool>>,
+    lexers: Vec<(bool, Vec<(u32, StateID)>)>,
+    lexer_ids: HashMap<(bool, Vec<(u32, StateID)>), u32>,
+    transitions: HashMap<(u32, u8), u32>,
+    rows: Vec<Arc<Row>>,
+    input: Vec<u8>,
+    state: State,
+}
+
+impl Parser {
+    pub fn new(grammar: Grammar) -> Result<Self, String> {
+        let mut regexes = grammar.terminals
+            .iter()
+            .map(|ast| {
+                let mut builder = RegexBuilder::new();
+                let expr = builder.mk(ast).map_err(|e| format!("Invalid regex `{:?}`: {}", ast, e))?;
+
+                Ok(builder.into_regex(expr))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let initial = regexes.iter_mut().map(|regex| regex.initial_state()).collect();
+        let mut by_lhs = vec![Vec::new(); grammar.nonterminals.len()];
+
+        for (idx, rule) in grammar.rules.iter().enumerate() {
+            by_lhs[rule.lhs as usize].push(idx as u32);
+        }
+
+        let mut nullable = vec![false; grammar.nonterminals.len()];
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for rule in &grammar.rules {
+                if nullable[rule.lhs as usize] {
+                    continue;
+                }
+
+                let empty = rule.rhs.iter().all(|symbol| match symbol {
+                    Symbol::Terminal(_) => false,
+                    Symbol::Nonterminal(n) => nullable[*n as usize],
+                });
+
+                if empty {
+                    nullable[rule.lhs as usize] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut parser = Self {
+            grammar: Arc::new(grammar),
+            regexes,
+            initial,
+            by_lhs: Arc::new(by_lhs),
+            nullable: Arc::new(nullable),
+            lexers: Vec::new(),
+            lexer_ids: HashMap::new(),
+            transitions: HashMap::new(),
+            rows: Vec::new(),
+            input: Vec::new(),
+            state: State { rows: 1, lexer: 0, word: false, offset: 0 },
+        };
+
+        let start = parser.by_lhs[parser.grammar.start as usize]
+            .iter()
+            .map(|&rule| Item { rule, dot: 0, origin: 0 })
+            .collect();
+
+        parser.push_row(start, DEAD, 0);
+        parser.state.lexer = parser.rows[0].lexer;
+
+        Ok(parser)
+    }
+
+    fn next_symbol(&self, item: Item) -> Option<Symbol> {
+        self.grammar.rules[item.rule as usize].rhs.get(item.dot as usize).copied()
+    }
+
+    fn intern(&mut self, fresh: bool, lexemes: Vec<(u32, StateID)>) -> u32 {
+        let key = (fresh, lexemes);
+
+        if let Some(&id) = self.lexer_ids.get(&key) {
+            return id;
+        }
+
+        let id = self.lexers.len() as u32;
+
+        self.lexers.push(key.clone());
+        self.lexer_ids.insert(key, id);
+
+        id
+    }
+
+    /// Completes and predicts `seed` into a new row; returns `false` if the
+    /// row would be empty.
+    fn push_row(&mut self, seed: Vec<Item>, source: u32, end: u32) -> bool {
+        if seed.is_empty() {
+            return false;
+        }
+
+        let index = self.rows.len() as u32;
+        let mut seen: HashSet<Item> = seed.iter().copied().collect();
+        let mut items = seed;
+        let mut expected = Vec::new();
+        let mut idx = 0;
+
+        while idx < items.len() {
+            let item = items[idx];
+            let mut added = Vec::new();
+
+            idx += 1;
+
+            match self.next_symbol(item) {
+                None if item.origin != index => {
+                    let lhs = Symbol::Nonterminal(self.grammar.rules[item.rule as usize].lhs);
+
+                    for &parent in &self.rows[item.origin as usize].items {
+                        if self.next_symbol(parent) == Some(lhs) {
+                            added.push(Item { dot: parent.dot + 1, ..parent });
+                        }
+                    }
+                }
+                // Completions at the same position are covered by skipping
+                // over nullable nonterminals when they are predicted
+                None => {}
+                Some(Symbol::Nonterminal(n)) => {
+                    for &rule in &self.by_lhs[n as usize] {
+                        added.push(Item { rule, dot: 0, origin: index });
+                    }
+
+                    if self.nullable[n as usize] {
+                        added.push(Item { dot: item.dot + 1, ..item });
+                    }
+                }
+                Some(Symbol::Terminal(t)) => expected.push(t),
+            }
+
+            for item in added {
+                if seen.insert(item) {
+                    items.push(item);
+                }
+            }
+        }
+
+        // Nothing may follow a complete top-level value, not even ignored
+        // text, so generation can stop there
+        if !expected.is_empty() || index == 0 || !self.completes_start(&items) {
+            expected.extend(self.grammar.ignore);
+        }
+
+        expected.sort_unstable();
+        expected.dedup();
+
+        let lexemes: Vec<(u32, StateID)> = expected
+            .into_iter()
+            .map(|t| (t, self.initial[t as usize]))
+            .collect();
+
+        let resume = if self.grammar.single_ignore {
+            let lexemes = lexemes.iter().copied().filter(|&(t, _)| Some(t) != self.grammar.ignore).collect();
+
+            self.intern(true, lexemes)
+        } else {
+            self.intern(true, lexemes.clone())
+        };
+
+        let lexer = self.intern(true, lexemes);
+
+        self.rows.push(Arc::new(Row { items, lexer, resume, source, end }));
+
+        true
+    }
+
+    fn completes_start(&self, items: &[Item]) -> bool {
+        items.iter().any(|&item| {
+            item.origin == 0
+                && self.grammar.rules[item.rule as usize].lhs == self.grammar.start
+                && self.next_symbol(item).is_none()
+        })
+    }
+
+    fn extend(&mut self, lexer: u32, byte: u8) -> Option<u32> {
+        if let Some(&next) = self.transitions.get(&(lexer, byte)) {
+            return (next != DEAD).then_some(next);
+        }
+
+        let lexemes: Vec<(u32, StateID)> = self.lexers[lexer as usize].1
+            .iter()
+            .filter_map(|&(t, state)| {
+                let next = self.regexes[t as usize].transition(state, byte);
+
+                (!next.is_dead()).then_some((t, next))
+            })
+            .collect();
+
+        let next = if lexemes.is_empty() {
+            DEAD
+        } else {
+            self.intern(false, lexemes)
+        };
+
+        self.transitions.insert((lexer, byte), next);
+
+        (next != DEAD).then_some(next)
+    }
+
+    /// Ends the current lexeme and returns the resulting number of rows with
+    /// the lexer state that starts the next lexeme.
+    fn finish(&mut self, state: State) -> Option<(u32, u32)> {
+        // Rows above `state` are left over from a sibling branch; the next
+        // one can be reused if it was built by finishing the same lexemes at
+        // the same offset
+        let reusable = |row: &&Arc<Row>| row.source == state.lexer && row.end == state.offset;
+
+        if let Some(row) = self.rows.get(state.rows as usize).filter(reusable) {
+            let lexer = row.lexer;
+
+            self.rows.truncate(state.rows as usize + 1);
+
+            return Some((state.rows + 1, lexer));
+        }
+
+        self.rows.truncate(state.rows as usize);
+
+        let (fresh, lexemes) = &self.lexers[state.lexer as usize];
+
+        if *fresh {
+            return None;
+        }
+
+        let accepted: Vec<u32> = lexemes
+            .iter()
+            .filter(|&&(t, state)| self.regexes[t as usize].is_accepting(state))
+            .map(|&(t, _)| t)
+            .collect();
+
+        let top = &self.rows[state.rows as usize - 1];
+        let seed = top.items
+            .iter()
+            .filter_map(|&item| match self.next_symbol(item) {
+                Some(Symbol::Terminal(t)) if accepted.contains(&t) => Some(Item { dot: item.dot + 1, ..item }),
+                _ => None,
+            })
+            .collect();
+
+        if self.push_row(seed, state.lexer, state.offset) {
+            return Some((state.rows + 1, self.rows[state.rows as usize].lexer));
+        }
+
+        if self.grammar.ignore.is_some_and(|ignore| accepted.contains(&ignore)) {
+            return Some((state.rows, self.rows[state.rows as usize - 1].resume));
+        }
+
+        None
+    }
+
+    fn step(&mut self, state: State, byte: u8) -> Option<State> {
+        let word = is_word(byte);
+
+        // Bytes past the current state are only kept for the path the
+        // latest steps took
+        self.input.truncate(state.offset as usize);
+        self.input.push(byte);
+
+        let offset = state.offset + 1;
+
+        if let Some(lexer) = self.extend(state.lexer, byte) {
+            return Some(State { lexer, word, offset, ..state });
+        }
+
+        if self.grammar.separate_words && state.word && word {
+            return None;
+        }
+
+        let (rows, lexer) = self.finish(state)?;
+        let lexer = self.extend(lexer, byte)?;
+
+        Some(State { rows, lexer, word, offset })
+    }
+
+    pub fn routes(&mut self, trie: &TokenTrie) -> Vec<u32> {
+        let state = self.state;
+        let routes = trie.walk(state, |state, byte| self.step(state, byte));
+
+        self.rows.truncate(state.rows as usize);
+
+        routes
+    }
+
+    /// Like `routes()`, for tokens that have to start with `prefix`, which is
+    /// not part of the input.
+    pub fn routes_after(&mut self, trie: &TokenTrie, prefix: &[u8]) -> Vec<u32> {
+        self.routes_where(trie, prefix, |_| true)
+    }
+
+    /// Like `routes_after()`, keeping only the tokens `keep` accepts; it sees
+    /// the parser as if the token had been fed.
+    pub fn routes_where(&mut self, trie: &TokenTrie, prefix: &[u8], mut keep: impl FnMut(&mut Self) -> bool) -> Vec<u32> {
+        let state = self.state;
+        let routes = trie.walk_filtered((0, state), |(matched, state), byte, ends| {
+            let next = match prefix.get(matched) {
+                Some(&expected) => (byte == expected).then_some((matched + 1, state))?,
+                None => (matched, self.step(state, byte)?),
+            };
+
+            if !ends || next.0 < prefix.len() {
+                return Some((next, false));
+            }
+
+            self.state = next.1;
+
+            Some((next, keep(self)))
+        });
+
+        self.state = state;
+        self.rows.truncate(state.rows as usize);
+
+        routes
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Returns to a state this parser was in earlier. Rows are only ever
+    /// appended above the current state, so everything below it is intact.
+    pub fn restore(&mut self, state: State) {
+        self.rows.truncate(state.rows as usize);
+        self.state = state;
+    }
+
+    /// Whether the input so far is a complete sentence, ending any pending
+    /// lexeme.
+    pub fn is_accepting(&mut self) -> bool {
+        let state = self.state;
+        let accepting = self.accepting(state);
+
+        self.rows.truncate(state.rows as usize);
+
+        accepting
+    }
+
+    fn accepting(&mut self, state: State) -> bool {
+        let rows = if self.lexers[state.lexer as usize].0 {
+            Some(state.rows)
+        } else {
+            self.finish(state).map(|(rows, _)| rows)
+        };
+
+        rows.is_some_and(|rows| self.completes_start(&self.rows[rows as usize - 1].items))
+    }
+
+    /// Bytes that every valid continuation starts with, stopping where the
+    /// input could also end. Ignored text is never forced, so continuations
+    /// that start with it are left out, except for the separator a forced
+    /// keyword needs after a word.
+    pub fn forced(&mut self) -> Vec<u8> {
+        let mut state = self.state;
+        let mut forced = Vec::new();
+
+        while forced.len() < MAX_FORCED && !self.accepting(state) {
+            if let Some((bytes, next)) = self.forced_literal(state) {
+                forced.extend(bytes);
+                state = next;
+
+                continue;
+            }
+
+            let Some((byte, next)) = self.forced_byte(state) else { break };
+
+            forced.push(byte);
+            state = next;
+        }
+
+        self.rows.truncate(self.state.rows as usize);
+
+        forced
+    }
+
+    /// The rest of a literal terminal when it is the only lexeme that can
+    /// continue or follow `state`, with a space before it where the grammar
+    /// requires one.
+    fn forced_literal(&mut self, state: State) -> Option<(Vec<u8>, State)> {
+        let ignore = self.grammar.ignore;
+        let (fresh, lexemes) = &self.lexers[state.lexer as usize];
+        let fresh = *fresh;
+        let pending: Vec<(u32, StateID)> = lexemes.iter().copied().filter(|&(t, _)| Some(t) != ignore).collect();
+
+        if !fresh && !pending.is_empty() {
+            let [(t, _)] = pending[..] else { return None };
+            let literal = self.grammar.literals[t as usize].clone()?;
+
+            // The bytes since the last row are a prefix of the literal (up to
+            // case); find where it leaves off
+            let rest = (0..=literal.len()).find_map(|skip| {
+                let rest = literal.as_bytes().get(skip..)?;
+                let lexer = rest.iter().try_fold(state.lexer, |lexer, &byte| self.extend(lexer, byte))?;
+                let complete = self.lexers[lexer as usize].1
+                    .iter()
+                    .any(|&(u, id)| u == t && self.regexes[t as usize].is_accepting(id));
+
+                complete.then(|| rest.to_vec())
+            })?;
+
+            if !rest.is_empty() {
+                return self.feed_from(state, rest);
+            }
+        }
+
+        let lexer = if fresh { state.lexer } else { self.finish(state)?.1 };
+        let next = &self.lexers[lexer as usize].1;
+        let expected: Vec<u32> = next.iter().map(|&(t, _)| t).filter(|&t| Some(t) != ignore).collect();
+
+        let [t] = expected[..] else { return None };
+        let literal = self.grammar.literals[t as usize].clone()?.into_bytes();
+
+        if let Some(forced) = self.feed_from(state, literal.clone()) {
+            return Some(forced);
+        }
+
+        ignore?;
+
+        self.feed_from(state, [b" ".as_slice(), &literal].concat())
+    }
+
+    /// The only byte that continues `state` with something other than
+    /// ignored text.
+    fn forced_byte(&mut self, state: State) -> Option<(u8, State)> {
+        let mut next = None;
+
+        for byte in 0..=255 {
+            let Some(candidate) = self.step(state, byte) else { continue };
+
+            let ignored = self.lexers[candidate.lexer as usize].1
+                .iter()
+                .all(|&(t, _)| Some(t) == self.grammar.ignore);
+
+            if !ignored {
+                if next.is_some() {
+                    return None;
+                }
+
+                next = Some((byte, candidate));
+            }
+        }
+
+        // Every byte finishes the same lexemes from `state`, so the rows the
+        // chosen byte built are still in place
+        next
+    }
+
+    fn feed_from(&mut self, state: State, bytes: Vec<u8>) -> Option<(Vec<u8>, State)> {
+        let state = bytes.iter().try_fold(state, |state, &byte| self.step(state, byte))?;
+
+        Some((bytes, state))
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) -> bool {
+        let mut state = self.state;
+
+        for &byte in bytes {
+            let Some(next) = self.step(state, byte) else {
+                self.rows.truncate(self.state.rows as usize);
+
+                return false;
+            };
+
+            state = next;
+        }
+
+        self.rows.truncate(state.rows as usize);
+        self.state = state;
+
+        true
+    }
+
+    fn terminal_name(&self, t: u32) -> String {
+        let literal = self.grammar.literals[t as usize].clone();
+
+        literal.unwrap_or_else(|| self.grammar.names[t as usize].clone())
+    }
+
+    /// Names of the terminals the pending lexeme can still become or the next
+    /// one can start, ignored text aside.
+    pub fn expected(&mut self) -> Vec<String> {
+        let state = self.state;
+        let mut terminals: Vec<u32> = self.lexers[state.lexer as usize].1.iter().map(|&(t, _)| t).collect();
+
+        if !self.lexers[state.lexer as usize].0 {
+            if let Some((_, lexer)) = self.finish(state) {
+                terminals.extend(self.lexers[lexer as usize].1.iter().map(|&(t, _)| t));
+            }
+
+            self.rows.truncate(state.rows as usize);
+        }
+
+        terminals.sort_unstable();
+        terminals.dedup();
+
+        terminals
+            .into_iter()
+            .filter(|&t| Some(t) != self.grammar.ignore)
+            .map(|t| self.terminal_name(t))
+            .collect()
+    }
+
+    /// Parse tree of the input, ending any pending lexeme; `None` unless the
+    /// input is a complete sentence. Of several parses, the first found is
+    /// returned.
+    pub fn tree(&mut self) -> Option<Node> {
+        let state = self.state;
+        let rows = if self.lexers[state.lexer as usize].0 {
+            Some(state.rows)
+        } else {
+            self.finish(state).map(|(rows, _)| rows)
+        };
+
+        let tree = rows.and_then(|rows| self.derive(self.grammar.start, 0, rows - 1, &mut Vec::new()));
+
+        self.rows.truncate(state.rows as usize);
+
+        tree
+    }
+
+    /// Runs `f` on the parser as if `bytes` had been fed, then returns to
+    /// the current state; `None` if `bytes` can't be fed. The rows built on
+    /// the way are left for other lookaheads that end the same lexemes.
+    pub fn lookahead<T>(&mut self, bytes: &[u8], f: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        let state = self.state;
+        let next = bytes.iter().try_fold(state, |state, &byte| self.step(state, byte))?;
+
+        self.state = next;
+
+        let result = f(self);
+
+        self.state = state;
+
+        Some(result)
+    }
+
+    /// Texts of the literal terminals.
+    pub fn literals(&self) -> impl Iterator<Item = &str> {
+        self.grammar.literals.iter().flatten().map(String::as_str)
+    }
+
+    /// Input up to the end of the last complete lexeme.
+    pub fn complete(&self) -> &[u8] {
+        &self.input[..self.rows[self.state.rows as usize - 1].end as usize]
+    }
+
+    /// Input after the last complete lexeme.
+    pub fn pending(&self) -> &[u8] {
+        &self.input[self.complete().len()..self.state.offset as usize]
+    }
+
+    /// Parse tree of the complete lexemes read so far. Rules that are still
+    /// open at the end of the input have the children read so far; the
+    /// pending lexeme is left out.
+    pub fn partial(&mut self) -> Node {
+        let start = Node::Rule(self.grammar.nonterminals[self.grammar.start as usize].clone(), Vec::new());
+
+        self.partials().into_iter().next().unwrap_or(start)
+    }
+
+    /// Like `partial()`, with a tree for every way the last complete lexeme
+    /// can be read, since the lexemes after it may rule out any of them.
+    /// Empty before the first lexeme.
+    pub fn partials(&mut self) -> Vec<Node> {
+        let row = self.state.rows - 1;
+
+        // The items that read the last lexeme lead up to the start rule
+        let scanned: Vec<Item> = self.rows[row as usize].items
+            .iter()
+            .copied()
+            .filter(|&item| item.dot > 0 && matches!(self.symbol(item.rule, item.dot - 1), Symbol::Terminal(_)))
+            .collect();
+
+        let mut trees: Vec<Node> = Vec::new();
+
+        for item in scanned {
+            if let Some(tree) = self.open(item, row, None, &mut Vec::new()) {
+                if !trees.contains(&tree) {
+                    trees.push(tree);
+                }
+            }
+        }
+
+        trees
+    }
+
+    fn symbol(&self, rule: u32, idx: u32) -> Symbol {
+        self.grammar.rules[rule as usize].rhs[idx as usize]
+    }
+
+    /// Whether the first `dot` symbols of `rule` can span rows `origin` to
+    /// `row`.
+    fn spans(&self, rule: u32, dot: u32, origin: u32, row: u32) -> bool {
+        if dot == 0 {
+            return row == origin;
+        }
+
+        self.rows[row as usize].items.contains(&Item { rule, dot, origin })
+    }
+
+    /// Text of the lexeme of terminal `t` that ends at `row`, without the
+    /// ignored text before it.
+    fn lexeme(&mut self, t: u32, row: u32) -> String {
+        let start = self.rows[row as usize - 1].end as usize;
+        let end = self.rows[row as usize].end as usize;
+        let bytes = self.input[start..end].to_vec();
+        let skip = (0..bytes.len()).find(|&skip| self.matches(t, &bytes[skip..])).unwrap_or(0);
+
+        String::from_utf8_lossy(&bytes[skip..]).into_owned()
+    }
+
+    fn matches(&mut self, t: u32, bytes: &[u8]) -> bool {
+        let regex = &mut self.regexes[t as usize];
+        let mut state = self.initial[t as usize];
+
+        for &byte in bytes {
+            state = regex.transition(state, byte);
+
+            if state.is_dead() {
+                return false;
+            }
+        }
+
+        regex.is_accepting(state)
+    }
+
+    /// Node for nonterminal `n` spanning rows `origin` to `row`. `path` holds
+    /// the rule spans being derived, so unit cycles end.
+    fn derive(&mut self, n: u32, origin: u32, row: u32, path: &mut Vec<(u32, u32, u32)>) -> Option<Node> {
+        let name = self.grammar.nonterminals[n as usize].clone();
+
+        if origin == row && self.nullable[n as usize] {
+            return Some(Node::Rule(name, Vec::new()));
+        }
+
+        for rule in self.by_lhs[n as usize].clone() {
+            let len = self.grammar.rules[rule as usize].rhs.len() as u32;
+
+            if len == 0 || !self.spans(rule, len, origin, row) || path.contains(&(rule, origin, row)) {
+                continue;
+            }
+
+            path.push((rule, origin, row));
+
+            let children = self.children(rule, len, origin, row, path);
+
+            path.pop();
+
+            if let Some(children) = children {
+                return Some(Node::Rule(name, children));
+            }
+        }
+
+        None
+    }
+
+    /// Nodes for the first `dot` symbols of `rule`, spanning rows `origin` to
+    /// `row`.
+    fn children(&mut self, rule: u32, dot: u32, origin: u32, row: u32, path: &mut Vec<(u32, u32, u32)>) -> Option<Vec<Node>> {
+        if dot == 0 {
+            return (row == origin).then(Vec::new);
+        }
+
+        match self.symbol(rule, dot - 1) {
+            Symbol::Terminal(t) => {
+                if row == origin || !self.spans(rule, dot - 1, origin, row - 1) {
+                    return None;
+                }
+
+                let mut nodes = self.children(rule, dot - 1, origin, row - 1, path)?;
+
+                nodes.push(Node::Lexeme(self.terminal_name(t), self.lexeme(t, row)));
+
+                Some(nodes)
+            }
+            Symbol::Nonterminal(n) => {
+                for split in (origin..=row).rev() {
+                    if !self.spans(rule, dot - 1, origin, split) {
+                        continue;
+                    }
+
+                    let Some(child) = self.derive(n, split, row, path) else { continue };
+                    let Some(mut nodes) = self.children(rule, dot - 1, origin, split, path) else { continue };
+
+                    splice(&mut nodes, child);
+
+                    return Some(nodes);
+                }
+
+                None
+            }
+        }
+    }
+
+    /// Tree for the open `item`, whose symbols before the dot end at `row`,
+    /// with `child` as the partial node of the symbol after the dot.
+    fn open(&mut self, item: Item, row: u32, child: Option<Node>, seen: &mut Vec<(Item, u32)>) -> Option<Node> {
+        if seen.contains(&(item, row)) {
+            return None;
+        }
+
+        let mut children = self.children(item.rule, item.dot, item.origin, row, &mut Vec::new())?;
+
+        if let Some(child) = child {
+            splice(&mut children, child);
+        }
+
+        let lhs = self.grammar.rules[item.rule as usize].lhs;
+        let node = Node::Rule(self.grammar.nonterminals[lhs as usize].clone(), children);
+
+        if lhs == self.grammar.start && item.origin == 0 {
+            return Some(node);
+        }
+
+        let parents: Vec<Item> = self.rows[item.origin as usize].items
+            .iter()
+            .copied()
+            .filter(|&parent| self.next_symbol(parent) == Some(Symbol::Nonterminal(lhs)))
+            .collect();
+
+        seen.push((item, row));
+
+        let tree = parents.into_iter().find_map(|parent| self.open(parent, item.origin, Some(node.clone()), seen));
+
+        seen.pop();
+
+        tree
+    }
+}
+
+/// Appends `child` to `nodes`, or its children if it is a helper rule.
+fn splice(nodes: &mut Vec<Node>, child: Node) {
+    match child {
+        Node::Rule(name, children) if name.contains('#') => nodes.extend(children),
+        child => nodes.push(child),
+    }
+}
+
+fn is_word(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOCABULARY: &[&str] = &[
+        "a", "b", "x", "ab", "ba", "aax", "1", "2", "12", "+", "1+", "+2", " ", "  ", " +", "SELECT", "SEL", "ECT",
+        "select", " name", "name", "na", "me", ",", ", ", " FROM", "FROM", " users", "\n",
+    ];
+
+    fn trie() -> TokenTrie {
+        TokenTrie::new(VOCABULARY.iter().enumerate().map(|(id, token)| (token.as_bytes(), id as u32)))
+    }
+
+    fn parser(grammar: &str) -> Parser {
+        Parser::new(Grammar::from_lark(grammar).unwrap()).unwrap()
+    }
+
+    fn accepts(grammar: &str, input: &str) -> bool {
+        let mut parser = parser(grammar);
+
+        parser.feed(input.as_bytes()) && parser.is_accepting()
+    }
+
+    /// Every token offered by `routes()` must be accepted by `feed()` and
+    /// every other token rejected, at each step of `path`.
+    fn check_routes(grammar: &str, path: &[&str]) {
+        let trie = trie();
+        let mut prefix = String::new();
+
+        for &token in path.iter().chain([""].iter()) {
+            let mut routes = parser(grammar);
+
+            assert!(routes.feed(prefix.as_bytes()));
+
+            let allowed = routes.routes(&trie);
+
+            for (id, candidate) in VOCABULARY.iter().enumerate() {
+                let mut fed = parser(grammar);
+
+                fed.feed(prefix.as_bytes());
+
+                assert_eq!(
+                    allowed.contains(&(id as u32)),
+                    fed.feed(candidate.as_bytes()),
+                    "after {:?}: {:?}",
+                    prefix,
+                    candidate,
+                );
+            }
+
+            prefix.push_str(token);
+        }
+    }
+
+    #[test]
+    fn nullable_rules() {
+        let grammar = "start: a b \"x\"\na: | \"a\"\nb: a a";
+
+        for input in ["x", "ax", "aax", "aaax"] {
+            assert!(accepts(grammar, input), "{}", input);
+        }
+
+        assert!(!accepts(grammar, "aaaax"));
+        assert!(!accepts(grammar, "b"));
+    }
+
+    #[test]
+    fn left_recursion() {
+        let grammar = "start: start \"+\" NUMBER | NUMBER\nNUMBER: /[0-9]+/";
+
+        assert!(accepts(grammar, "1+22+333"));
+        assert!(!accepts(grammar, "1++2"));
+        assert!(!accepts(grammar, "+1"));
+    }
+
+    #[test]
+    fn ambiguous_grammar() {
+        let grammar = "start: e\ne: e \"+\" e | \"1\" | \"2\"";
+
+        assert!(accepts(grammar, "1+2+1+2"));
+        assert!(!accepts(grammar, "12"));
+    }
+
+    #[test]
+    fn ignore_between_lexemes() {
+        let grammar = "start: \"a\" \"b\"\n%ignore \" \"";
+
+        assert!(accepts(grammar, "a b"));
+        assert!(accepts(grammar, "  a   b"));
+        assert!(!accepts(grammar, "a x"));
+
+        let grammar = "start: \"a\" \"b\" \"+\" \"a\"\n%ignore \" \"\n%separate_words";
+
+        assert!(accepts(grammar, "a b+a"));
+        assert!(!accepts(grammar, "ab+a"));
+        check_routes(grammar, &["a", " ", "b"]);
+
+        let grammar = "start: \"a\" \"b\"\n%ignore \" \"\n%single_ignore";
+
+        assert!(accepts(grammar, "a b"));
+        assert!(!accepts(grammar, "a  b"));
+        check_routes(grammar, &["a", " "]);
+    }
+
+    #[test]
+    fn failed_feed_keeps_state() {
+        let mut parser = parser("start: \"a\" \"b\" \"x\"");
+
+        assert!(parser.feed(b"a"));
+        assert!(!parser.feed(b"bb"));
+        assert!(parser.feed(b"bx"));
+    }
+
+    #[test]
+    fn routes_agree_with_feed() {
+        check_routes("start: a b \"x\"\na: | \"a\"\nb: a a", &["a", "a", "x"]);
+        check_routes("start: start \"+\" NUMBER | NUMBER\nNUMBER: /[0-9]+/\n%ignore \" \"", &["1", " +", "2", "12"]);
+        check_routes(
+            "start: \"SELECT\"i NAME (\",\" NAME)* \"FROM\" NAME\nNAME: /[a-z]+/\n%ignore /[ \\n]+/",
+            &["SEL", "ECT", " name", ", ", "na", "me", " FROM", " users"],
+        );
+    }
+
+    #[test]
+    fn regex_grammar() {
+        let trie = trie();
+        let mut parser = Parser::new(Grammar::from_regex("[0-9]{1,3}")).unwrap();
+        let expected: Vec<u32> = ["1", "2", "12"]
+            .iter()
+            .map(|token| VOCABULARY.iter().position(|t| t == token).unwrap() as u32)
+            .collect();
+
+        let mut routes = parser.routes(&trie);
+
+        routes.sort();
+
+        assert_eq!(routes, expected);
+        assert!(parser.feed(b"12"));
+        assert!(parser.routes(&trie).len() == 2);
+        assert!(parser.feed(b"1"));
+        assert!(parser.routes(&trie).is_empty());
+    }
+
+    #[test]
+    fn sql_grammar() {
+        let grammar = &crate::sql::grammar(&Default::default());
+
+        for query in [
+            "SELECT * FROM users",
+            "select name, COUNT(*) AS n from users u join orders o on o.user_id = u.id group by name order by n desc limit 10;",
+            "WITH big AS (SELECT user_id FROM orders WHERE total > 100.5) SELECT name FROM users WHERE id IN (SELECT user_id FROM big)",
+            "SELECT CASE WHEN status = 'it''s' THEN 1 ELSE 0 END FROM orders WHERE email IS NOT NULL AND NOT id BETWEEN 1 AND 5",
+        ] {
+            assert!(accepts(grammar, query), "{}", query);
+        }
+
+        for query in [
+            "SELECT FROM users",
+            "SELECT name FROM",
+            "SELECT name users ,",
+            "DELETE FROM users",
+            "SELECTname FROM users",
+            "SELECT * FROMusers",
+            "SELECT 1AS n",
+        ] {
+            assert!(!accepts(grammar, &format!("{} ;", query)), "{}", query);
+        }
+    }
+
+    #[test]
+    fn accepting_states() {
+        let grammar = "start: \"[\" [NUMBER (\",\" NUMBER)*] \"]\"\nNUMBER: /[0-9]+/\n%ignore \" \"";
+        let trie = trie();
+        let mut parser = parser(grammar);
+
+        for (input, accepting) in [("", false), ("[", false), (" 1", false), ("2 ", false), ("]", true)] {
+            assert!(parser.feed(input.as_bytes()), "{}", input);
+            assert_eq!(parser.is_accepting(), accepting, "{}", input);
+        }
+
+        // Nothing, not even ignored whitespace, follows the complete value
+        assert!(parser.routes(&trie).is_empty());
+        assert!(!parser.feed(b" "));
+
+        // A pending lexeme is ended before checking
+        let mut parser = self::parser("start: NUMBER\nNUMBER: /[0-9]+/");
+
+        assert!(!parser.is_accepting());
+        assert!(parser.feed(b"12"));
+        assert!(parser.is_accepting());
+        assert!(parser.feed(b"3"));
+        assert!(parser.is_accepting());
+
+        let mut parser = Parser::new(Grammar::from_regex("ab?")).unwrap();
+
+        assert!(parser.feed(b"a"));
+        assert!(parser.is_accepting());
+        assert!(parser.feed(b"b"));
+        assert!(parser.is_accepting());
+        assert!(parser.routes(&trie).is_empty());
+
+        assert!(accepts("start: \"a\"*", ""));
+        assert!(!accepts("start: \"a\"+", ""));
+    }
+
+    #[test]
+    fn restore_earlier_state() {
+        let grammar = "start: \"SELECT\" NAME (\",\" NAME)* \"FROM\" NAME\nNAME: /[a-z]+/\n%ignore \" \"";
+        let trie = trie();
+        let mut parser = parser(grammar);
+
+        assert!(parser.feed(b"SELECT na"));
+
+        let state = parser.state();
+        let routes = parser.routes(&trie);
+
+        assert!(parser.feed(b"me, name FROM"));
+        assert_ne!(parser.routes(&trie), routes);
+
+        parser.restore(state);
+
+        assert_eq!(parser.routes(&trie), routes);
+        assert!(parser.feed(b"me FROM users"));
+        assert!(parser.is_accepting());
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let grammar = "start: \"a\" (\"b\" | \"c\") \"x\"";
+        let trie = trie();
+        let mut parser = parser(grammar);
+
+        assert!(parser.feed(b"a"));
+
+        let mut fork = parser.clone();
+
+        assert!(fork.feed(b"c"));
+        assert!(parser.feed(b"b"));
+        assert!(!parser.feed(b"c"));
+        assert!(fork.feed(b"x"));
+        assert!(fork.is_accepting());
+        assert!(!parser.is_accepting());
+        assert_eq!(parser.routes(&trie), vec![2]);
+    }
+
+    #[test]
+    fn forced_continuations() {
+        let grammar = "start: \"ORDER\" \"BY\" NAME | \"ORDER\" \"BYTES\"\nNAME: /[a-z]+/\n%ignore \" \"";
+        let mut parser = parser(grammar);
+
+        assert_eq!(parser.forced(), b"ORDERBY");
+        assert!(parser.feed(b"ORDER "));
+        assert_eq!(parser.forced(), b"BY");
+        assert!(parser.feed(b"B"));
+        assert_eq!(parser.forced(), b"Y");
+
+        // Forcing leaves the parser where it was
+        assert!(parser.feed(b"Y n"));
+        assert_eq!(parser.forced(), b"");
+        assert!(parser.is_accepting());
+
+        let mut parser = self::parser("start: \"select_\" NAME \"_column\" \";\"\nNAME: \"a\"");
+
+        assert_eq!(parser.forced(), b"select_a_column;");
+
+        // Whole keywords are forced, with the space they need after a word
+        let mut parser = self::parser(&crate::sql::grammar(&Default::default()));
+
+        assert!(parser.feed(b"SELECT * FROM users WHERE id = 1 ORDER"));
+        assert_eq!(parser.forced(), b" BY");
+        assert!(parser.feed(b" b"));
+        assert_eq!(parser.forced(), b"Y");
+        assert!(parser.feed(b"y name DESC LIMIT 1 OFFSET 2"));
+        assert_eq!(parser.forced(), b"");
+
+        let mut parser = self::parser("start: \"a\" start");
+
+        assert_eq!(parser.forced().len(), MAX_FORCED);
+    }
+
+    /// `tree` as an s-expression of rule names and lexeme texts.
+    fn show(tree: &Node) -> String {
+        match tree {
+            Node::Rule(name, children) => {
+                let children: Vec<String> = children.iter().map(show).collect();
+
+                format!("({} {})", name, children.join(" ")).replace(" )", ")")
+            }
+            Node::Lexeme(_, text) => text.clone(),
+        }
+    }
+
+    #[test]
+    fn parse_trees() {
+        let mut parser = parser("start: item (\",\" item)* [\";\"]\nitem: NAME | \"-\"\nNAME: /[a-z]+/\n%ignore \" \"");
+
+        assert_eq!(parser.tree(), None);
+        assert!(parser.feed(b" ab ,  c"));
+        assert_eq!(show(&parser.tree().unwrap()), "(start (item ab) , (item c))");
+        assert!(parser.feed(b",-;"));
+        assert_eq!(show(&parser.tree().unwrap()), "(start (item ab) , (item c) , (item -) ;)");
+
+        let Node::Rule(_, children) = parser.tree().unwrap() else { panic!() };
+
+        assert_eq!(children[0], Node::Rule("item".to_string(), vec![Node::Lexeme("NAME".to_string(), "ab".to_string())]));
+        assert_eq!(children[5], Node::Lexeme(";".to_string(), ";".to_string()));
+
+        let mut parser = self::parser("start: start \"+\" NUMBER | NUMBER\nNUMBER: /[0-9]+/");
+
+        assert!(parser.feed(b"1+22+3"));
+        assert_eq!(show(&parser.tree().unwrap()), "(start (start (start 1) + 22) + 3)");
+
+        let mut parser = self::parser("start: a b \"x\"\na: | \"a\"\nb: a a");
+
+        assert!(parser.feed(b"ax"));
+        assert_eq!(show(&parser.tree().unwrap()), "(start (a a) (b) x)");
+    }
+
+    #[test]
+    fn partial_trees() {
+        let mut parser = parser("start: item (\",\" item)* [\";\"]\nitem: NAME | \"-\"\nNAME: /[a-z]+/\n%ignore \" \"");
+
+        assert!(parser.feed(b" ab ,  c"));
+        assert_eq!(show(&parser.partial()), "(start (item ab) ,)");
+        assert!(parser.feed(b",-;"));
+
+        // Nothing has ended the `;` lexeme yet
+        assert_eq!(show(&parser.partial()), "(start (item ab) , (item c) , (item -))");
+
+        let mut parser = self::parser(&crate::sql::grammar(&Default::default()));
+
+        assert_eq!(show(&parser.partial()), "(start)");
+        assert!(parser.expected().contains(&"SELECT".to_string()));
+        assert!(parser.feed(b"SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE to"));
+
+        let partial = show(&parser.partial());
+
+        assert!(partial.starts_with("(start (statement (select_stmt (select_body (select_core SELECT"), "{}", partial);
+        assert!(partial.ends_with("(where_clause WHERE))))))))))))))"), "{}", partial);
+        assert!(partial.contains("(from_clause FROM (table_or_subquery orders))"), "{}", partial);
+
+        // The pending lexeme can still become a longer name or end as one
+        let expected = parser.expected();
+
+        assert!(expected.contains(&"COLUMN_NAME".to_string()), "{:?}", expected);
+        assert!(expected.contains(&".".to_string()), "{:?}", expected);
+        assert!(!expected.contains(&"FROM".to_string()), "{:?}", expected);
+
+        assert!(parser.tree().is_none());
+        assert_eq!(parser.complete(), b"SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE");
+        assert!(parser.feed(b"tal > 1)"));
+        assert!(parser.tree().is_some());
+    }
+
+    #[test]
+    fn routes_after_prefix() {
+        let trie = trie();
+        let mut parser = parser("start: NAME \",\" NAME\nNAME: /[a-z]+/");
+
+        // "na" was removed from the prompt; "na" itself and "name" start
+        // with it
+        let mut routes = parser.routes_after(&trie, b"na");
+
+        routes.sort();
+
+        assert_eq!(routes, vec![20, 21]);
+
+        // "na" falls short of the removed "nam"
+        let mut parser = self::parser("start: \"e\" \",\"");
+        let mut routes = parser.routes_after(&trie, b"nam");
+
+        routes.sort();
+
+        assert_eq!(routes, vec![20]);
+        assert!(parser.feed(b"e"));
+    }
+}
@@ -0,0 +1,1507 @@
+//! What the parse tree of a SQL statement says about the statement: the
+//! clause the input is in, the tables it refers to and whether its names
+//! resolve against the schema.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::catalog::{Affinity, Catalog, Column};
+use crate::functions::{self, SQLITE};
+use crate::parser::Node;
+
+/// Clause names of the rules that start one.
+const CLAUSES: &[(&str, &str)] = &[
+    ("with_clause", "with"),
+    ("select_core", "select"),
+    ("from_clause", "from"),
+    ("join_constraint", "on"),
+    ("where_clause", "where"),
+    ("group_by", "group_by"),
+    ("order_by", "order_by"),
+    ("over_clause", "over"),
+    ("limit", "limit"),
+    ("capped_select", "limit"),
+    ("insert_stmt", "insert"),
+    ("insert_source", "values"),
+    ("update_stmt", "update"),
+    ("assignment", "set"),
+    ("delete_stmt", "delete"),
+    ("drop_stmt", "drop"),
+    ("pragma_stmt", "pragma"),
+];
+
+/// Where a partial statement stands.
+#[derive(Debug, PartialEq)]
+pub struct Position {
+    /// Clause the end of the input is in, if any has started.
+    pub clause: Option<&'static str>,
+    /// Tables (or their aliases) the innermost query has named so far.
+    pub tables: Vec<String>,
+}
+
+/// Rules along the right edge of `tree`, outermost first: the ones a partial
+/// tree still has open.
+pub fn spine(tree: &Node) -> Vec<&Node> {
+    let mut spine = vec![tree];
+
+    while let Some(Node::Rule(_, children)) = spine.last() {
+        match children.last() {
+            Some(child @ Node::Rule(..)) => spine.push(child),
+            _ => break,
+        }
+    }
+
+    spine
+}
+
+/// Rule name of `node`, if it is a rule.
+pub fn rule(node: &Node) -> Option<&str> {
+    match node {
+        Node::Rule(name, _) => Some(name),
+        Node::Lexeme(..) => None,
+    }
+}
+
+/// Child rules of `node` named `name`.
+pub fn children<'a>(node: &'a Node, name: &'a str) -> impl Iterator<Item = &'a Node> {
+    let children = match node {
+        Node::Rule(_, children) => children.as_slice(),
+        Node::Lexeme(..) => &[],
+    };
+
+    children.iter().filter(move |child| rule(child) == Some(name))
+}
+
+/// Texts of the lexemes of terminal `terminal` among the children of `node`.
+pub fn lexemes<'a>(node: &'a Node, terminal: &'a str) -> impl Iterator<Item = &'a str> {
+    let children = match node {
+        Node::Rule(_, children) => children.as_slice(),
+        Node::Lexeme(..) => &[],
+    };
+
+    children.iter().filter_map(move |child| match child {
+        Node::Lexeme(name, text) if name == terminal => Some(text.as_str()),
+        _ => None,
+    })
+}
+
+/// Name a table is known by in its query: the alias if it has one.
+fn source_name(table: &Node) -> Option<&str> {
+    lexemes(table, "ALIAS").next().or_else(|| lexemes(table, "TABLE_NAME").next())
+}
+
+pub fn position(tree: &Node) -> Position {
+    let spine = spine(tree);
+
+    let clause = spine
+        .iter()
+        .rev()
+        .find_map(|node| CLAUSES.iter().find(|(name, _)| rule(node) == Some(*name)))
+        .map(|&(_, clause)| clause);
+
+    // The query the input is in: the innermost `select_core` (the last one
+    // of a compound `SELECT` in its `ORDER BY`), or a statement that names a
+    // table itself
+    let query = spine.iter().rev().find_map(|&node| match rule(node) {
+        Some("select_core" | "update_stmt" | "delete_stmt" | "insert_stmt") => Some(node),
+        Some("select_body") => children(node, "select_core").last(),
+        _ => None,
+    });
+
+    let mut tables = Vec::new();
+
+    if let Some(query) = query {
+        tables.extend(lexemes(query, "TABLE_NAME").map(str::to_string));
+
+        for from in children(query, "from_clause") {
+            let joined = children(from, "join_clause").flat_map(|join| children(join, "table_or_subquery"));
+
+            for table in children(from, "table_or_subquery").chain(joined) {
+                tables.extend(source_name(table).map(str::to_string));
+            }
+        }
+    }
+
+    Position { clause, tables }
+}
+
+/// Identifier text without the double quotes around it.
+fn unquote(name: &str) -> &str {
+    name.strip_prefix('"').and_then(|name| name.strip_suffix('"')).unwrap_or(name)
+}
+
+fn same(a: &str, b: &str) -> bool {
+    unquote(a).eq_ignore_ascii_case(unquote(b))
+}
+
+/// Table or subquery a query reads from, under the name the query uses for
+/// it (as written), and the schema table or CTE it reads, if any. `columns`
+/// is `None` where the schema doesn't tell.
+struct Source {
+    name: String,
+    table: String,
+    columns: Option<Vec<Column>>,
+}
+
+/// What a column reference resolved to: `column` of `table` (a schema table
+/// or CTE, empty for a subquery), which the query calls `source` (as written,
+/// empty if it can't name it). All three are empty but `column` for a result
+/// column alias.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub source: String,
+    pub table: String,
+    pub column: String,
+    pub affinity: Option<Affinity>,
+}
+
+/// Column references of checked statements with what they resolved to, and
+/// the result columns of each statement that is a `SELECT`.
+pub struct Resolved<'a> {
+    pub references: Vec<(&'a Node, Reference)>,
+    pub columns: Vec<Option<Vec<Column>>>,
+}
+
+impl Resolved<'_> {
+    pub fn reference(&self, node: &Node) -> Option<&Reference> {
+        self.references.iter().find(|(other, _)| std::ptr::eq(*other, node)).map(|(_, reference)| reference)
+    }
+}
+
+/// Sources of one query, the aliases of its result columns and the columns
+/// `USING` merged. Column references fall back to the `outer` scope. A scope
+/// is `closed` once no more sources can be added to it.
+struct Scope {
+    sources: Vec<Source>,
+    aliases: Vec<String>,
+    merged: Vec<String>,
+    outer: Option<usize>,
+    closed: bool,
+}
+
+/// Schema rules for SQL statements: tables and columns have to exist in
+/// `catalog` (when there is one) or a CTE and be in scope where they are
+/// used, functions get the number and kinds of arguments they take,
+/// aggregate queries may only use the columns they group by outside
+/// aggregate functions, and subqueries nest at most `max_depth` levels deep.
+#[derive(Clone, Default)]
+pub struct Checker {
+    pub catalog: Option<Catalog>,
+    pub max_depth: Option<u32>,
+}
+
+impl Checker {
+    /// First problem with the complete statements in `tree`.
+    pub fn check(&self, tree: &Node) -> Result<(), String> {
+        Walker::new(self, Vec::new(), false).statements(tree)
+    }
+
+    /// Like `check()`, returning what the names in `tree` resolve to.
+    pub fn resolve<'a>(&'a self, tree: &'a Node) -> Result<Resolved<'a>, String> {
+        let mut walker = Walker::new(self, Vec::new(), false);
+
+        walker.statements(tree)?;
+
+        Ok(Resolved { references: walker.references, columns: walker.columns })
+    }
+
+    /// First problem with the partial statement in `tree` that no
+    /// continuation can fix.
+    pub fn check_partial(&self, tree: &Node) -> Result<(), String> {
+        Walker::new(self, spine(tree), true).statements(tree)
+    }
+}
+
+struct Walker<'a> {
+    checker: &'a Checker,
+    spine: Vec<&'a Node>,
+    partial: bool,
+    scopes: Vec<Scope>,
+    ctes: Vec<Source>,
+    depth: u32,
+    references: Vec<(&'a Node, Reference)>,
+    columns: Vec<Option<Vec<Column>>>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(checker: &'a Checker, spine: Vec<&'a Node>, partial: bool) -> Self {
+        Self {
+            checker,
+            spine,
+            partial,
+            scopes: Vec::new(),
+            ctes: Vec::new(),
+            depth: 0,
+            references: Vec::new(),
+            columns: Vec::new(),
+        }
+    }
+
+    fn statements(&mut self, node: &'a Node) -> Result<(), String> {
+        let Node::Rule(name, children) = node else {
+            return Ok(());
+        };
+
+        if name != "statement" {
+            return children.iter().try_for_each(|child| self.statements(child));
+        }
+
+        // Each statement of a script only sees the schema
+        self.scopes.clear();
+
+        let mut columns = None;
+
+        for child in children {
+            match rule(child) {
+                Some("select_stmt" | "capped_select") => {
+                    columns = self.select(child, None)?;
+                }
+                Some("insert_stmt") => self.insert(child)?,
+                Some("update_stmt" | "delete_stmt") => self.target(child)?,
+                Some("drop_stmt") => {
+                    for table in lexemes(child, "TABLE_NAME") {
+                        self.table(table)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.columns.push(columns);
+
+        Ok(())
+    }
+
+    /// Whether `node` is still open in a partial tree.
+    fn open(&self, node: &Node) -> bool {
+        self.spine.iter().any(|open| std::ptr::eq(*open, node))
+    }
+
+    fn scope(&mut self, sources: Vec<Source>, outer: Option<usize>, closed: bool) -> usize {
+        self.scopes.push(Scope { sources, aliases: Vec::new(), merged: Vec::new(), outer, closed });
+        self.scopes.len() - 1
+    }
+
+    /// Columns of the table `name`, or `None` without a schema. CTEs in
+    /// scope shadow the tables of the schema.
+    fn table(&self, name: &str) -> Result<Option<Vec<Column>>, String> {
+        if let Some(cte) = self.ctes.iter().rev().find(|cte| same(&cte.name, name)) {
+            return Ok(cte.columns.clone());
+        }
+
+        let Some(catalog) = &self.checker.catalog else {
+            return Ok(None);
+        };
+
+        let Some(table) = catalog.tables.iter().find(|table| same(&table.name, name)) else {
+            return Err(format!("Unknown table `{}`", unquote(name)));
+        };
+
+        Ok(Some(table.columns.clone()))
+    }
+
+    /// Checks the `select_stmt` or `capped_select` in `node` and returns the
+    /// its result columns, where they are known.
+    fn select(&mut self, node: &'a Node, outer: Option<usize>) -> Result<Option<Vec<Column>>, String> {
+        if self.checker.max_depth.is_some_and(|max| self.depth > max) {
+            return Err(format!("Subqueries nest deeper than the limit of {}", self.checker.max_depth.unwrap_or(0)));
+        }
+
+        self.depth += 1;
+
+        let ctes = self.ctes.len();
+        let mut columns = None;
+        let mut cores = 0;
+        let mut last = None;
+
+        for body in children(node, "select_body") {
+            let Node::Rule(_, parts) = body else { continue };
+
+            for part in parts {
+                match rule(part) {
+                    Some("with_clause") => self.with(part, outer)?,
+                    Some("select_core") => {
+                        let (names, scope) = self.core(part, outer)?;
+
+                        columns = columns.or(Some(names));
+                        cores += 1;
+                        last = Some((part, scope));
+                    }
+                    Some("order_by") => {
+                        let scope = last.map_or_else(|| self.scope(Vec::new(), outer, true), |(_, scope)| scope);
+
+                        self.expr(part, scope)?;
+
+                        // A compound `SELECT` can only be ordered by its result
+                        // columns
+                        if let Some((core, scope)) = last.filter(|_| cores == 1 && !self.open(part)) {
+                            self.grouped(core, scope, children(part, "ordering_term"))?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // `LIMIT` and `OFFSET` don't see the query's own columns
+        let Node::Rule(_, parts) = node else { unreachable!() };
+        let scope = self.scope(Vec::new(), outer, true);
+
+        for part in parts.iter().filter(|part| rule(part) != Some("select_body")) {
+            self.expr(part, scope)?;
+        }
+
+        self.ctes.truncate(ctes);
+        self.depth -= 1;
+
+        Ok(columns.flatten())
+    }
+
+    /// Adds the CTEs of a `WITH` as tables of the query it belongs to; each
+    /// one also sees the ones before it.
+    fn with(&mut self, node: &'a Node, outer: Option<usize>) -> Result<(), String> {
+        for cte in children(node, "cte") {
+            let mut names = lexemes(cte, "ALIAS");
+            let Some(name) = names.next() else {
+                continue;
+            };
+
+            let names: Vec<String> = names.map(|name| unquote(name).to_string()).collect();
+            let mut columns = None;
+
+            // A CTE is no deeper than the query it belongs to
+            if let Some(select) = children(cte, "select_stmt").next() {
+                self.depth -= 1;
+                columns = self.select(select, outer)?;
+                self.depth += 1;
+            }
+
+            if !names.is_empty() {
+                if !self.open(cte) && columns.as_ref().is_some_and(|columns| columns.len() != names.len()) {
+                    return Err(format!(
+                        "`{}` names {} columns but its query returns {}",
+                        unquote(name),
+                        names.len(),
+                        columns.map_or(0, |columns| columns.len()),
+                    ));
+                }
+
+                let query = columns.unwrap_or_default();
+                let affinity = |idx: usize| query.get(idx).and_then(|column| column.affinity);
+
+                columns = Some(names.into_iter().enumerate().map(|(idx, name)| Column { name, affinity: affinity(idx) }).collect());
+            }
+
+            self.ctes.push(Source { name: name.to_string(), table: unquote(name).to_string(), columns });
+        }
+
+        Ok(())
+    }
+
+    fn core(&mut self, node: &'a Node, outer: Option<usize>) -> Result<(Option<Vec<Column>>, usize), String> {
+        let Node::Rule(_, parts) = node else { unreachable!() };
+
+        // Sources come after the result columns, so these can only be checked
+        // once the input has gone past `FROM`
+        let closed = !self.partial
+            || !self.open(node)
+            || matches!(parts.last().and_then(rule), Some("where_clause" | "group_by"));
+        let scope = self.scope(Vec::new(), outer, closed);
+
+        for from in children(node, "from_clause") {
+            self.from(from, scope)?;
+        }
+
+        let mut names = Some(Vec::new());
+
+        for column in children(node, "result_column") {
+            let alias = lexemes(column, "ALIAS").next();
+
+            self.scopes[scope].aliases.extend(alias.map(str::to_string));
+
+            let Node::Rule(_, items) = column else { continue };
+
+            match items.as_slice() {
+                [Node::Lexeme(star, _)] if star == "*" => {
+                    let columns: Option<Vec<Vec<Column>>> =
+                        self.scopes[scope].sources.iter().map(|source| source.columns.clone()).collect();
+
+                    names = names.zip(columns.map(|columns| columns.concat())).map(|(names, columns)| [names, columns].concat());
+                }
+                [Node::Lexeme(_, qualifier), ..] => {
+                    let Some(source) = self.scopes[scope].sources.iter().find(|source| same(&source.name, qualifier)) else {
+                        if closed {
+                            return Err(format!("Unknown table `{}`", unquote(qualifier)));
+                        }
+
+                        continue;
+                    };
+
+                    names = names.zip(source.columns.clone()).map(|(names, columns)| [names, columns].concat());
+                }
+                [expr, ..] => {
+                    self.expr(expr, scope)?;
+
+                    let name = alias.or_else(|| match expr {
+                        Node::Rule(..) => column_name(expr),
+                        Node::Lexeme(..) => None,
+                    });
+
+                    let name = name.map(unquote).unwrap_or_default().to_string();
+                    let affinity = self.affinity(expr, scope);
+
+                    // Columns without a name still count
+                    if let Some(names) = &mut names {
+                        names.push(Column { name, affinity });
+                    }
+                }
+                [] => {}
+            }
+        }
+
+        for part in parts.iter().filter(|part| matches!(rule(part), Some("where_clause" | "group_by"))) {
+            self.expr(part, scope)?;
+
+            // Windows are over the rows these pick
+            if windowed(part) {
+                let clause = if rule(part) == Some("where_clause") { "WHERE" } else { "GROUP BY" };
+
+                return Err(format!("Window functions can't be used in `{}`", clause));
+            }
+        }
+
+        // Until the query is done, `GROUP BY` may still list the columns
+        if !self.open(node) {
+            let (_, having) = groups(node);
+
+            self.grouped(node, scope, children(node, "result_column").chain(having))?;
+        }
+
+        Ok((names, scope))
+    }
+
+    /// In an aggregate query, checks that the columns `exprs` refer to are
+    /// grouped by or inside aggregate functions.
+    fn grouped(&self, core: &'a Node, scope: usize, exprs: impl Iterator<Item = &'a Node>) -> Result<(), String> {
+        let results: Vec<&Node> = children(core, "result_column").collect();
+        let (keys, having) = groups(core);
+
+        if keys.is_empty() && !results.iter().copied().chain(having).any(aggregates) {
+            return Ok(());
+        }
+
+        // Ordinals and aliases in `GROUP BY` stand for result columns
+        let result = |idx: usize| children(results.get(idx)?, "expr").next();
+        let keys: Vec<&Node> = keys
+            .into_iter()
+            .map(|key| {
+                let ordinal = bare(key, "literal").and_then(|literal| lexemes(literal, "NUMBER").next()?.parse::<usize>().ok());
+                let alias = bare(key, "column_ref")
+                    .filter(|column| !self.local(column, scope))
+                    .and_then(|column| {
+                        let name = lexemes(column, "COLUMN_NAME").next()?;
+
+                        results.iter().position(|result| lexemes(result, "ALIAS").any(|alias| same(alias, name)))
+                    });
+
+                match (ordinal, alias) {
+                    (Some(ordinal), _) => result(ordinal.wrapping_sub(1)).unwrap_or(key),
+                    (None, Some(idx)) => result(idx).unwrap_or(key),
+                    (None, None) => key,
+                }
+            })
+            .collect();
+
+        for expr in exprs {
+            if let Some(column) = self.ungrouped(expr, &keys, scope) {
+                return Err(format!("`{}` has to be in GROUP BY or inside an aggregate function", column));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// First column reference in `node` that is neither grouped by `keys` nor
+    /// inside an aggregate function.
+    fn ungrouped(&self, node: &Node, keys: &[&Node], scope: usize) -> Option<String> {
+        let Node::Rule(name, parts) = node else {
+            return None;
+        };
+
+        if keys.contains(&node) || aggregate(node) || name == "select_stmt" {
+            return None;
+        }
+
+        match (name.as_str(), parts.as_slice()) {
+            ("column_ref", _) => {
+                let grouped = keys.iter().any(|key| bare(key, "column_ref").is_some_and(|key| same_column(key, node)));
+
+                (!grouped && self.local(node, scope)).then(|| reference(node))
+            }
+            ("result_column", [Node::Lexeme(..), ..]) => Some(reference(node)),
+            _ => parts.iter().find_map(|part| self.ungrouped(part, keys, scope)),
+        }
+    }
+
+    /// Whether the column reference `column` is to a source of `scope`
+    /// itself rather than to a result column or an outer query.
+    fn local(&self, column: &Node, scope: usize) -> bool {
+        let qualifier = lexemes(column, "QUALIFIER").next();
+        let name = lexemes(column, "COLUMN_NAME").next().unwrap_or_default();
+
+        self.scopes[scope].sources.iter().any(|source| match qualifier {
+            Some(qualifier) => same(&source.name, qualifier),
+            None => source.columns.as_ref().is_none_or(|columns| columns.iter().any(|c| same(&c.name, name))),
+        })
+    }
+
+    fn from(&mut self, node: &'a Node, scope: usize) -> Result<(), String> {
+        let Node::Rule(_, parts) = node else { unreachable!() };
+
+        for part in parts {
+            match rule(part) {
+                Some("table_or_subquery") => self.source(part, scope)?,
+                Some("join_clause") => {
+                    for table in children(part, "table_or_subquery") {
+                        self.source(table, scope)?;
+                    }
+
+                    for constraint in children(part, "join_constraint") {
+                        self.constraint(constraint, scope)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn source(&mut self, node: &'a Node, scope: usize) -> Result<(), String> {
+        let table = lexemes(node, "TABLE_NAME").next();
+        let columns = match table {
+            Some(table) => self.table(table)?,
+            // Subqueries in `FROM` don't see the query they are in
+            None => match children(node, "select_stmt").next() {
+                Some(select) => self.select(select, self.scopes[scope].outer)?,
+                None => None,
+            },
+        };
+
+        // A subquery without an alias can't be named, but its columns can
+        let name = lexemes(node, "ALIAS").next().or(table).unwrap_or_default().to_string();
+        let table = table.map(unquote).unwrap_or_default().to_string();
+
+        self.scopes[scope].sources.push(Source { name, table, columns });
+
+        Ok(())
+    }
+
+    /// `ON` sees the sources joined so far; `USING` columns have to be in
+    /// the joined table and an earlier one.
+    fn constraint(&mut self, node: &'a Node, scope: usize) -> Result<(), String> {
+        let closed = std::mem::replace(&mut self.scopes[scope].closed, true);
+
+        self.expr(node, scope)?;
+        self.scopes[scope].closed = closed;
+
+        let Some((joined, earlier)) = self.scopes[scope].sources.split_last() else {
+            return Ok(());
+        };
+
+        for column in lexemes(node, "COLUMN_NAME") {
+            let has = |source: &Source| source.columns.as_ref().is_none_or(|columns| columns.iter().any(|c| same(&c.name, column)));
+
+            if !has(joined) || !earlier.iter().any(has) {
+                return Err(format!("Unknown column `{}` in `USING`", unquote(column)));
+            }
+        }
+
+        let merged: Vec<String> = lexemes(node, "COLUMN_NAME").map(|column| unquote(column).to_string()).collect();
+
+        self.scopes[scope].merged.extend(merged);
+
+        Ok(())
+    }
+
+    fn insert(&mut self, node: &'a Node) -> Result<(), String> {
+        let Some(table) = lexemes(node, "TABLE_NAME").next() else {
+            return Ok(());
+        };
+
+        let columns = self.table(table)?;
+
+        for column in lexemes(node, "COLUMN_NAME") {
+            if columns.as_ref().is_some_and(|columns| !columns.iter().any(|c| same(&c.name, column))) {
+                return Err(format!("Unknown column `{}.{}`", unquote(table), unquote(column)));
+            }
+        }
+
+        let scope = self.scope(Vec::new(), None, true);
+
+        for source in children(node, "insert_source") {
+            match children(source, "select_stmt").next() {
+                Some(select) => {
+                    self.select(select, None)?;
+                }
+                None => self.expr(source, scope)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `UPDATE` and `DELETE`, which only see their target table.
+    fn target(&mut self, node: &'a Node) -> Result<(), String> {
+        let Some(table) = lexemes(node, "TABLE_NAME").next() else {
+            return Ok(());
+        };
+
+        let columns = self.table(table)?;
+        let source = Source { name: table.to_string(), table: unquote(table).to_string(), columns: columns.clone() };
+        let scope = self.scope(vec![source], None, true);
+
+        for assignment in children(node, "assignment") {
+            for column in lexemes(assignment, "COLUMN_NAME") {
+                if columns.as_ref().is_some_and(|columns| !columns.iter().any(|c| same(&c.name, column))) {
+                    return Err(format!("Unknown column `{}.{}`", unquote(table), unquote(column)));
+                }
+            }
+
+            self.expr(assignment, scope)?;
+        }
+
+        for clause in children(node, "where_clause") {
+            self.expr(clause, scope)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the column references and subqueries inside `node`.
+    fn expr(&mut self, node: &'a Node, scope: usize) -> Result<(), String> {
+        let Node::Rule(name, children) = node else {
+            return Ok(());
+        };
+
+        match name.as_str() {
+            "column_ref" => self.column(node, scope),
+            "select_stmt" => self.select(node, Some(scope)).map(drop),
+            "function" => {
+                self.function(node, scope)?;
+
+                children.iter().try_for_each(|child| self.expr(child, scope))
+            }
+            _ => children.iter().try_for_each(|child| self.expr(child, scope)),
+        }
+    }
+
+    /// Checks that a function call has as many arguments as the function
+    /// takes, of the kinds it takes.
+    fn function(&self, node: &Node, scope: usize) -> Result<(), String> {
+        let Some(name) = lexemes(node, "FUNCTION_NAME").next() else {
+            return Ok(());
+        };
+
+        // A `,` at the end of an open call already stands for the argument
+        // after it
+        let args: Vec<&Node> = children(node, "expr").collect();
+        let count = if args.is_empty() { 0 } else { lexemes(node, ",").count() + 1 };
+        let Some(function) = functions::find(SQLITE, name, count) else {
+            return Err(format!("Unknown function `{}`", name));
+        };
+
+        let over = children(node, "over_clause").next().is_some();
+
+        if function.window && !over && !self.open(node) {
+            return Err(format!("`{}` needs an `OVER` clause", function.name));
+        }
+
+        if over && !function.window && !function.aggregate {
+            return Err(format!("`{}` can't run over a window", function.name));
+        }
+
+        // Until its `)`, a call may still get the arguments it lacks, and its
+        // last argument may go on
+        let open = self.open(node) && lexemes(node, ")").next().is_none();
+
+        if lexemes(node, "*").next().is_some() && function.name != "count" {
+            return Err(format!("`{}` doesn't take `*`", function.name));
+        }
+
+        let pending = open && count < function.required;
+
+        if !function.takes(count) && !pending {
+            return Err(format!("`{}` takes {}, not {}", function.name, function.arity(), count));
+        }
+
+        for (idx, arg) in args.iter().enumerate() {
+            if open && idx + 1 == count {
+                break;
+            }
+
+            let Some(affinity) = self.affinity(arg, scope) else {
+                continue;
+            };
+
+            if !function.arg(idx).accepts(affinity) {
+                return Err(format!(
+                    "Type mismatch: argument {} of `{}` has to be {}, not {}",
+                    idx + 1,
+                    function.name,
+                    function.arg(idx).name(),
+                    affinity.name(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Affinity of the values of `expr`, where it is a column, a literal or a
+    /// function with a fixed result.
+    fn affinity(&self, expr: &Node, scope: usize) -> Option<Affinity> {
+        if let Some(literal) = bare(expr, "literal") {
+            let number = lexemes(literal, "NUMBER").next().map(|number| match number.contains('.') {
+                true => Affinity::Real,
+                false => Affinity::Integer,
+            });
+
+            return number.or_else(|| lexemes(literal, "STRING").next().map(|_| Affinity::Text));
+        }
+
+        if let Some(function) = bare(expr, "function") {
+            let name = lexemes(function, "FUNCTION_NAME").next()?;
+
+            return functions::find(SQLITE, name, children(function, "expr").count())?.result;
+        }
+
+        let column = bare(expr, "column_ref")?;
+        let qualifier = lexemes(column, "QUALIFIER").next();
+        let name = lexemes(column, "COLUMN_NAME").next()?;
+        let mut next = Some(scope);
+
+        while let Some(idx) = next {
+            let scope = &self.scopes[idx];
+            let sources = scope.sources.iter().filter(|source| qualifier.is_none_or(|qualifier| same(&source.name, qualifier)));
+
+            for source in sources {
+                if let Some(column) = source.columns.iter().flatten().find(|column| same(&column.name, name)) {
+                    return column.affinity;
+                }
+            }
+
+            next = scope.outer;
+        }
+
+        None
+    }
+
+    /// Resolves a column reference in `scope`, then in the scopes around it.
+    fn column(&mut self, node: &'a Node, scope: usize) -> Result<(), String> {
+        let qualifier = lexemes(node, "QUALIFIER").next();
+        let column = lexemes(node, "COLUMN_NAME").next();
+
+        // A partial reference may only have its qualifier so far
+        if qualifier.is_none() && column.is_none() {
+            return Ok(());
+        }
+
+        let name = column.unwrap_or_default();
+        let mut next = Some(scope);
+        let mut closed = true;
+
+        while let Some(idx) = next {
+            let scope = &self.scopes[idx];
+            let has = |source: &Source| match (&source.columns, column) {
+                (Some(columns), Some(column)) => columns.iter().any(|c| same(&c.name, column)),
+                _ => true,
+            };
+
+            closed &= scope.closed;
+
+            let found = match qualifier {
+                Some(qualifier) => {
+                    let Some(source) = scope.sources.iter().find(|source| same(&source.name, qualifier)) else {
+                        next = scope.outer;
+
+                        continue;
+                    };
+
+                    if !has(source) {
+                        return Err(format!("Unknown column `{}.{}`", unquote(qualifier), unquote(name)));
+                    }
+
+                    Some(source)
+                }
+                None => {
+                    let known = scope.sources.iter().filter(|source| source.columns.is_some() && has(source)).count();
+
+                    if known > 1 && !scope.merged.iter().any(|merged| same(merged, name)) {
+                        return Err(format!("Ambiguous column `{}`", unquote(name)));
+                    }
+
+                    scope.sources.iter().find(|source| has(source))
+                }
+            };
+
+            let reference = match found {
+                Some(source) => {
+                    let declared = source.columns.iter().flatten().find(|c| same(&c.name, name));
+
+                    Reference {
+                        source: source.name.clone(),
+                        table: source.table.clone(),
+                        column: declared.map_or(unquote(name), |c| &c.name).to_string(),
+                        affinity: declared.and_then(|c| c.affinity),
+                    }
+                }
+                None if qualifier.is_none() && scope.aliases.iter().any(|alias| same(alias, name)) => Reference {
+                    source: String::new(),
+                    table: String::new(),
+                    column: unquote(name).to_string(),
+                    affinity: None,
+                },
+                None => {
+                    next = scope.outer;
+
+                    continue;
+                }
+            };
+
+            if column.is_some() {
+                self.references.push((node, reference));
+            }
+
+            return Ok(());
+        }
+
+        // A partial query may still add the source that has it
+        if self.partial && !closed {
+            return Ok(());
+        }
+
+        match qualifier {
+            Some(qualifier) => Err(format!("Unknown table `{}`", unquote(qualifier))),
+            None => Err(format!("Unknown column `{}`", unquote(name))),
+        }
+    }
+}
+
+/// The statements in `tree` as SQL with keywords and function names in upper
+/// case, column references qualified by their source where the query can name
+/// it and one space between lexemes, but none inside parentheses, before `,`,
+/// around `.` or between a function and its arguments. The `;` between them
+/// and text around them are left out.
+pub fn normalize(tree: &Node, resolved: &Resolved) -> Vec<String> {
+    children(tree, "statement").map(|statement| render_statement(statement, resolved)).collect()
+}
+
+fn render_statement(statement: &Node, resolved: &Resolved) -> String {
+    let mut words = Vec::new();
+
+    render(statement, resolved, &mut words);
+
+    let mut sql = String::new();
+    let mut previous: Option<(String, bool)> = None;
+
+    for (text, function) in words {
+        let glued = match &previous {
+            None => true,
+            Some((previous, function)) => {
+                matches!(previous.as_str(), "(" | ".")
+                    || matches!(text.as_str(), "," | ")" | ".")
+                    || (*function && text == "(")
+            }
+        };
+
+        if !glued {
+            sql.push(' ');
+        }
+
+        sql.push_str(&text);
+        previous = Some((text, function));
+    }
+
+    sql
+}
+
+/// Lexemes of `node` as `normalize()` writes them, each with whether it is a
+/// function name.
+fn render(node: &Node, resolved: &Resolved, words: &mut Vec<(String, bool)>) {
+    match node {
+        Node::Rule(_, children) => {
+            let source = resolved.reference(node).map(|reference| &reference.source).filter(|source| !source.is_empty());
+
+            match (source, lexemes(node, "COLUMN_NAME").next()) {
+                (Some(source), Some(column)) => {
+                    words.extend([(source.clone(), false), (".".to_string(), false), (column.to_string(), false)]);
+                }
+                _ => children.iter().for_each(|child| render(child, resolved, words)),
+            }
+        }
+        Node::Lexeme(terminal, text) => {
+            let keyword = terminal.eq_ignore_ascii_case(text) && text.bytes().all(|byte| byte.is_ascii_alphabetic() || byte == b'_');
+            let function = terminal == "FUNCTION_NAME";
+
+            if keyword || function {
+                words.push((text.to_ascii_uppercase(), function));
+            } else {
+                words.push((text.clone(), false));
+            }
+        }
+    }
+}
+
+/// Verdicts of `check_partial` for the ways to continue one input, keyed by
+/// what each continuation completes. Words that neither the schema, the
+/// functions, the grammar's literals nor the input so far contain are
+/// replaced by numbered placeholders in the key: the checker can only compare
+/// such names with each other, so continuations that differ in them alone
+/// share a verdict.
+pub struct Verdicts {
+    known: HashSet<String>,
+    start: usize,
+    verdicts: HashMap<Vec<u8>, bool>,
+}
+
+/// Runs of identifier characters in `text`, with their offsets.
+fn words(text: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (idx, byte) in text.iter().chain([b' '].iter()).enumerate() {
+        match (start, byte.is_ascii_alphanumeric() || *byte == b'_') {
+            (None, true) => start = Some(idx),
+            (Some(from), false) => {
+                words.push((from, &text[from..idx]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    words
+}
+
+impl Verdicts {
+    /// Cache for continuations of `input`, the complete part of which has
+    /// been checked already.
+    pub fn new<'a>(checker: &Checker, literals: impl Iterator<Item = &'a str>, input: &[u8]) -> Self {
+        let mut known = HashSet::new();
+        let mut learn = |text: &[u8]| {
+            known.extend(words(text).into_iter().map(|(_, word)| String::from_utf8_lossy(word).to_lowercase()));
+        };
+
+        literals.for_each(|literal| learn(literal.as_bytes()));
+        functions::names(SQLITE).iter().for_each(|name| learn(name.as_bytes()));
+        learn(input);
+
+        for table in checker.catalog.iter().flat_map(|catalog| &catalog.tables) {
+            learn(table.name.as_bytes());
+            table.columns.iter().for_each(|column| learn(column.name.as_bytes()));
+        }
+
+        Self { known, start: input.len(), verdicts: HashMap::new() }
+    }
+
+    /// Key of the continuation whose complete input is `complete`; `None`
+    /// if it completes nothing new.
+    pub fn key(&self, complete: &[u8]) -> Option<Vec<u8>> {
+        let added = complete.get(self.start..).filter(|added| !added.is_empty())?;
+        let mut key = Vec::with_capacity(added.len());
+        let mut unknown: Vec<&[u8]> = Vec::new();
+        let mut end = 0;
+
+        for (offset, word) in words(added) {
+            let text = String::from_utf8_lossy(word).to_lowercase();
+
+            key.extend_from_slice(&added[end..offset]);
+            end = offset + word.len();
+
+            if word[0].is_ascii_digit() || self.known.contains(&text) {
+                key.extend_from_slice(word);
+
+                continue;
+            }
+
+            let idx = unknown.iter().position(|other| other.eq_ignore_ascii_case(word)).unwrap_or_else(|| {
+                unknown.push(word);
+                unknown.len() - 1
+            });
+
+            key.push(0);
+            key.extend_from_slice(idx.to_string().as_bytes());
+        }
+
+        key.extend_from_slice(&added[end..]);
+
+        Some(key)
+    }
+
+    /// Verdict for the continuation with `key`, running `check` if no
+    /// continuation of the same shape was checked.
+    pub fn get(&mut self, key: Vec<u8>, check: impl FnOnce() -> bool) -> bool {
+        if let Some(&verdict) = self.verdicts.get(&key) {
+            return verdict;
+        }
+
+        let verdict = check();
+
+        self.verdicts.insert(key, verdict);
+
+        verdict
+    }
+}
+
+/// The `rule` node an expression is, if the expression is nothing else.
+fn bare<'n>(expr: &'n Node, rule: &str) -> Option<&'n Node> {
+    let mut node = expr;
+
+    loop {
+        let Node::Rule(name, children) = node else {
+            return None;
+        };
+
+        if name == rule {
+            return Some(node);
+        }
+
+        let [child] = children.as_slice() else {
+            return None;
+        };
+
+        node = child;
+    }
+}
+
+/// Name of the column an expression is, if it is a bare column reference.
+fn column_name(expr: &Node) -> Option<&str> {
+    bare(expr, "column_ref").and_then(|column| lexemes(column, "COLUMN_NAME").next())
+}
+
+/// Whether the column references `a` and `b` name the same column; an
+/// unqualified one matches any qualifier.
+fn same_column(a: &Node, b: &Node) -> bool {
+    let qualifiers = (lexemes(a, "QUALIFIER").next(), lexemes(b, "QUALIFIER").next());
+    let names = (lexemes(a, "COLUMN_NAME").next(), lexemes(b, "COLUMN_NAME").next());
+
+    matches!(names, (Some(a), Some(b)) if same(a, b)) && match qualifiers {
+        (Some(a), Some(b)) => same(a, b),
+        _ => true,
+    }
+}
+
+/// Whether `node` calls an aggregate function.
+fn aggregate(node: &Node) -> bool {
+    let Some(name) = lexemes(node, "FUNCTION_NAME").next().filter(|_| rule(node) == Some("function")) else {
+        return false;
+    };
+
+    // Over a window, it gives a value for every row
+    let over = children(node, "over_clause").next().is_some();
+
+    !over && functions::find(SQLITE, name, children(node, "expr").count()).is_some_and(|function| function.aggregate)
+}
+
+/// Whether `node` runs a function over a window outside subqueries.
+fn windowed(node: &Node) -> bool {
+    match node {
+        Node::Rule(name, _) if name == "over_clause" => true,
+        Node::Rule(name, children) if name != "select_stmt" => children.iter().any(windowed),
+        _ => false,
+    }
+}
+
+/// Whether `node` calls an aggregate function outside subqueries.
+fn aggregates(node: &Node) -> bool {
+    match node {
+        Node::Rule(name, children) if name != "select_stmt" => aggregate(node) || children.iter().any(aggregates),
+        _ => false,
+    }
+}
+
+/// `GROUP BY` expressions of `core` and its `HAVING` condition.
+fn groups(core: &Node) -> (Vec<&Node>, Option<&Node>) {
+    let Some(Node::Rule(_, parts)) = children(core, "group_by").next() else {
+        return (Vec::new(), None);
+    };
+
+    let having = parts.iter().position(|part| matches!(part, Node::Lexeme(name, _) if same(name, "HAVING")));
+    let (keys, having) = parts.split_at(having.unwrap_or(parts.len()));
+
+    (keys.iter().filter(|part| rule(part) == Some("expr")).collect(), having.iter().find(|part| rule(part).is_some()))
+}
+
+/// Text of a column reference, or of a `*` result column.
+fn reference(column: &Node) -> String {
+    let Node::Rule(_, children) = column else {
+        return String::new();
+    };
+
+    children
+        .iter()
+        .map(|child| match child {
+            Node::Lexeme(_, text) => unquote(text),
+            Node::Rule(..) => "",
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Grammar;
+    use crate::parser::Parser;
+
+    const SCHEMA: &str = "
+        CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT);
+        CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER, total REAL);
+    ";
+
+    fn parser(input: &str) -> Parser {
+        let grammar = crate::sql::grammar(&crate::sql::Options {
+            statements: crate::sql::Statement::ALL.to_vec(),
+            ..Default::default()
+        });
+        let mut parser = Parser::new(Grammar::from_lark(&grammar).unwrap()).unwrap();
+
+        assert!(parser.feed(input.as_bytes()), "{}", input);
+
+        parser
+    }
+
+    fn position_after(input: &str) -> Position {
+        position(&parser(input).partial())
+    }
+
+    fn checker(max_depth: Option<u32>) -> Checker {
+        Checker { catalog: Some(Catalog::from_ddl(SCHEMA).unwrap()), max_depth }
+    }
+
+    fn check(checker: &Checker, query: &str) -> Result<(), String> {
+        checker.check(&parser(query).tree().unwrap())
+    }
+
+    /// Like a session does: fine if any reading of the input is.
+    fn check_partial(checker: &Checker, input: &str) -> Result<(), String> {
+        let results: Vec<_> = parser(input).partials().iter().map(|tree| checker.check_partial(tree)).collect();
+
+        results.iter().find(|result| result.is_ok()).unwrap_or(&results[0]).clone()
+    }
+
+    #[test]
+    fn clauses_and_tables() {
+        for (input, clause, tables) in [
+            ("", None, vec![]),
+            ("SELECT name, ", Some("select"), vec![]),
+            ("SELECT name FROM users u JOIN orders ", Some("from"), vec!["u", "orders"]),
+            ("SELECT name FROM users u JOIN orders o ON o.user_id = ", Some("on"), vec!["u", "o"]),
+            ("SELECT * FROM users WHERE id IN (SELECT user_id FROM orders WHERE ", Some("where"), vec!["orders"]),
+            ("SELECT * FROM users WHERE id IN (SELECT user_id FROM orders) ", Some("where"), vec!["users"]),
+            ("SELECT * FROM users GROUP BY name HAVING ", Some("group_by"), vec!["users"]),
+            ("SELECT * FROM users ORDER BY ", Some("order_by"), vec!["users"]),
+            ("UPDATE users SET name = ", Some("set"), vec!["users"]),
+        ] {
+            let position = position_after(input);
+
+            assert_eq!(position.clause, clause, "{}", input);
+            assert_eq!(position.tables, tables, "{}", input);
+        }
+    }
+
+    #[test]
+    fn names_resolve_in_scope() {
+        let checker = checker(None);
+
+        for query in [
+            "SELECT name FROM users",
+            "SELECT u.name, o.total FROM users u JOIN orders AS o ON o.user_id = u.id",
+            "SELECT \"name\", USERS.Email FROM users WHERE id > 1 ORDER BY 1",
+            "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE orders.user_id = users.id)",
+            "SELECT name FROM users u WHERE EXISTS (SELECT 1 FROM orders WHERE user_id = u.id AND total > 10)",
+            "SELECT t.n, n FROM (SELECT name AS n FROM users) t",
+            "SELECT id, total FROM users JOIN orders USING (id)",
+            "SELECT name AS n FROM users GROUP BY n ORDER BY n",
+            "SELECT 1 + 1 LIMIT 1",
+            "UPDATE users SET name = 'x' WHERE id = 1",
+            "DELETE FROM orders WHERE total < 0",
+            "INSERT INTO orders (user_id, total) SELECT id, 1 FROM users",
+        ] {
+            assert_eq!(check(&checker, query), Ok(()), "{}", query);
+        }
+
+        for (query, error) in [
+            ("SELECT nope FROM users", "Unknown column `nope`"),
+            ("SELECT name FROM nope", "Unknown table `nope`"),
+            ("SELECT id FROM users, orders", "Ambiguous column `id`"),
+            ("SELECT x.name FROM users u", "Unknown table `x`"),
+            ("SELECT users.name FROM users u", "Unknown table `users`"),
+            ("SELECT u.total FROM users u", "Unknown column `u.total`"),
+            ("SELECT name FROM (SELECT id FROM users)", "Unknown column `name`"),
+            ("SELECT t.name FROM (SELECT id FROM users) t", "Unknown column `t.name`"),
+            ("SELECT u.* FROM users", "Unknown table `u`"),
+            ("SELECT name FROM users JOIN orders USING (email)", "Unknown column `email` in `USING`"),
+            ("SELECT name FROM users WHERE id = o.user_id", "Unknown table `o`"),
+            ("SELECT name FROM users u, (SELECT id FROM orders WHERE user_id = u.id) o", "Unknown table `u`"),
+            ("SELECT 1 LIMIT id", "Unknown column `id`"),
+            ("UPDATE users SET total = 1", "Unknown column `users.total`"),
+            ("INSERT INTO users (nope) VALUES (1)", "Unknown column `users.nope`"),
+            ("DROP TABLE nope", "Unknown table `nope`"),
+        ] {
+            assert_eq!(check(&checker, query), Err(error.to_string()), "{}", query);
+        }
+
+        // Without a schema anything goes
+        assert_eq!(check(&Checker::default(), "SELECT nope FROM nothing"), Ok(()));
+    }
+
+    #[test]
+    fn ctes() {
+        let checker = checker(Some(0));
+
+        for query in [
+            "WITH big AS (SELECT user_id, total AS t FROM orders) SELECT name, t FROM users JOIN big ON big.user_id = users.id",
+            "WITH a (x) AS (SELECT id FROM users), b AS (SELECT x + 1 AS y FROM a) SELECT y FROM b",
+            "WITH users AS (SELECT 1 AS one) SELECT one FROM users",
+        ] {
+            // CTEs don't nest in their query
+            assert_eq!(check(&checker, query), Ok(()), "{}", query);
+        }
+
+        for (query, error) in [
+            ("WITH a AS (SELECT id FROM users) SELECT name FROM a", "Unknown column `name`"),
+            ("WITH a (x, y) AS (SELECT id FROM users) SELECT x FROM a", "`a` names 2 columns but its query returns 1"),
+            ("WITH a AS (SELECT * FROM b), b AS (SELECT 1) SELECT * FROM a", "Unknown table `b`"),
+            ("SELECT * FROM (WITH a AS (SELECT 1) SELECT * FROM a), a", "Unknown table `a`"),
+        ] {
+            assert_eq!(check(&self::checker(None), query), Err(error.to_string()), "{}", query);
+        }
+
+        assert_eq!(check_partial(&checker, "WITH a (x) AS (SELECT id, "), Ok(()));
+        assert_eq!(check_partial(&checker, "WITH a AS (SELECT id FROM users) SELECT name FROM a "), Ok(()));
+        assert_eq!(
+            check_partial(&checker, "WITH a AS (SELECT id FROM users) SELECT name FROM a WHERE "),
+            Err("Unknown column `name`".to_string()),
+        );
+    }
+
+    #[test]
+    fn grouping() {
+        let checker = checker(None);
+
+        for query in [
+            "SELECT COUNT(*) FROM users",
+            "SELECT name, COUNT(*) FROM users GROUP BY name",
+            "SELECT u.name, SUM(o.total) FROM users u JOIN orders o ON o.user_id = u.id GROUP BY name HAVING COUNT(*) > 1",
+            "SELECT LOWER(name) AS n, MAX(id) FROM users GROUP BY n ORDER BY n",
+            "SELECT LOWER(name), MAX(id) FROM users GROUP BY 1 ORDER BY MAX(id)",
+            "SELECT LOWER(name), COUNT(*) AS c FROM users GROUP BY LOWER(name) ORDER BY c",
+            "SELECT name FROM users WHERE id IN (SELECT MAX(user_id) FROM orders WHERE total > users.id)",
+            "SELECT name, MAX(id, 1) FROM users",
+        ] {
+            assert_eq!(check(&checker, query), Ok(()), "{}", query);
+        }
+
+        for (query, error) in [
+            ("SELECT name, COUNT(*) FROM users", "`name`"),
+            ("SELECT *, COUNT(*) FROM users", "`*`"),
+            ("SELECT name FROM users GROUP BY email", "`name`"),
+            ("SELECT u.name FROM users u GROUP BY u.email", "`u.name`"),
+            ("SELECT name FROM users GROUP BY name HAVING id > 1", "`id`"),
+            ("SELECT name FROM users GROUP BY name ORDER BY id", "`id`"),
+        ] {
+            let error = format!("{} has to be in GROUP BY or inside an aggregate function", error);
+
+            assert_eq!(check(&checker, query), Err(error), "{}", query);
+        }
+
+        // `GROUP BY` can still come, or go on
+        for input in ["SELECT name, COUNT(*) FROM users ", "SELECT name, email, COUNT(*) FROM users GROUP BY name, "] {
+            assert_eq!(check_partial(&checker, input), Ok(()), "{}", input);
+        }
+
+        assert!(check_partial(&checker, "SELECT name, COUNT(*) FROM users ORDER BY ").is_err());
+    }
+
+    #[test]
+    fn function_calls() {
+        let checker = checker(None);
+
+        for query in [
+            "SELECT LOWER(name), COALESCE(email, name, 'none'), strftime('%Y', 'now', 'utc') FROM users",
+            "SELECT SUM(total), AVG(total + 1), COUNT(DISTINCT user_id) FROM orders",
+            "SELECT t.n FROM (SELECT LENGTH(name) AS n FROM users) t WHERE ABS(t.n) > 1",
+            "SELECT MAX(id, user_id), SUBSTR(UPPER('abc'), 1) FROM orders",
+        ] {
+            assert_eq!(check(&checker, query), Ok(()), "{}", query);
+        }
+
+        for (query, error) in [
+            ("SELECT LOWER(name, email) FROM users", "`lower` takes 1 argument, not 2"),
+            ("SELECT COALESCE(name) FROM users", "`coalesce` takes at least 2 arguments, not 1"),
+            ("SELECT SUM(*) FROM orders", "`sum` doesn't take `*`"),
+            ("SELECT SUM(name) FROM users", "Type mismatch: argument 1 of `sum` has to be a number, not TEXT"),
+            ("SELECT LOWER(id) FROM users", "Type mismatch: argument 1 of `lower` has to be text, not INTEGER"),
+            ("SELECT AVG(n) FROM (SELECT UPPER(name) AS n FROM users)", "Type mismatch: argument 1 of `avg` has to be a number, not TEXT"),
+            ("SELECT ROUND(total, 'x') FROM orders", "Type mismatch: argument 2 of `round` has to be a number, not TEXT"),
+        ] {
+            assert_eq!(check(&checker, query), Err(error.to_string()), "{}", query);
+        }
+
+        for input in ["SELECT SUBSTR(name, ", "SELECT LOWER(", "SELECT SUM(total "] {
+            assert_eq!(check_partial(&checker, input), Ok(()), "{}", input);
+        }
+
+        assert!(check_partial(&checker, "SELECT LOWER(name, ").is_err());
+        assert!(check_partial(&checker, "SELECT SUM(name, ").is_err());
+    }
+
+    #[test]
+    fn window_functions() {
+        let checker = checker(None);
+
+        for query in [
+            "SELECT id, ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY total DESC) FROM orders",
+            "SELECT user_id, SUM(total) OVER (PARTITION BY user_id), total FROM orders",
+            "SELECT o.id, LAG(o.total, 1, 0) OVER (ORDER BY o.id) AS previous FROM orders o",
+            "SELECT RANK() OVER () FROM users ORDER BY name",
+        ] {
+            assert_eq!(check(&checker, query), Ok(()), "{}", query);
+        }
+
+        for (query, error) in [
+            ("SELECT ROW_NUMBER() OVER (PARTITION BY nope) FROM orders", "Unknown column `nope`"),
+            ("SELECT RANK() OVER (ORDER BY u.total) FROM users u", "Unknown column `u.total`"),
+            ("SELECT ROW_NUMBER() FROM orders", "`row_number` needs an `OVER` clause"),
+            ("SELECT LOWER(name) OVER () FROM users", "`lower` can't run over a window"),
+            ("SELECT NTILE(name) OVER () FROM users", "Type mismatch: argument 1 of `ntile` has to be a number, not TEXT"),
+            ("SELECT id FROM orders WHERE RANK() OVER (ORDER BY total) < 3", "Window functions can't be used in `WHERE`"),
+        ] {
+            assert_eq!(check(&checker, query), Err(error.to_string()), "{}", query);
+        }
+
+        for input in ["SELECT ROW_NUMBER() ", "SELECT ROW_NUMBER() OVER (PARTITION BY id "] {
+            assert_eq!(check_partial(&checker, input), Ok(()), "{}", input);
+        }
+
+        assert!(check_partial(&checker, "SELECT ROW_NUMBER() OVER (PARTITION BY nope ORDER BY ").is_ok());
+        assert!(check_partial(&checker, "SELECT ROW_NUMBER() OVER (PARTITION BY nope) FROM orders WHERE ").is_err());
+    }
+
+    #[test]
+    fn resolved_statements() {
+        let checker = checker(None);
+        let tree = parser("select u.name, count(*) as n from users u join orders on user_id = u.id where total > 1.5 group by name").tree().unwrap();
+        let resolved = checker.resolve(&tree).unwrap();
+        let references: Vec<String> = resolved.references
+            .iter()
+            .map(|(_, reference)| format!("{}:{}.{}:{:?}", reference.source, reference.table, reference.column, reference.affinity))
+            .collect();
+        let columns: Vec<(&str, Option<Affinity>)> =
+            resolved.columns.iter().flatten().flatten().map(|column| (column.name.as_str(), column.affinity)).collect();
+
+        assert_eq!(
+            normalize(&tree, &resolved),
+            ["SELECT u.name, COUNT(*) AS n FROM users u JOIN orders ON orders.user_id = u.id WHERE orders.total > 1.5 GROUP BY u.name"],
+        );
+        assert_eq!(
+            references,
+            [
+                "orders:orders.user_id:Some(Integer)", "u:users.id:Some(Integer)", "u:users.name:Some(Text)",
+                "orders:orders.total:Some(Real)", "u:users.name:Some(Text)",
+            ],
+        );
+        assert_eq!(columns, [("name", Some(Affinity::Text)), ("n", Some(Affinity::Integer))]);
+
+        // Neither an unnamed subquery nor an alias can qualify a column
+        let tree = parser("SELECT n FROM (SELECT LOWER(name) AS n FROM users) ORDER BY n IN (1,2)").tree().unwrap();
+        let resolved = checker.resolve(&tree).unwrap();
+
+        assert_eq!(normalize(&tree, &resolved), ["SELECT n FROM (SELECT LOWER(users.name) AS n FROM users) ORDER BY n IN (1, 2)"]);
+        assert_eq!(resolved.columns[0].as_ref().unwrap()[0].affinity, Some(Affinity::Text));
+
+        // The prose around a fenced statement is left out
+        let fenced = crate::sql::grammar(&crate::sql::Options { trigger: Some("```sql\n".to_string()), ..Default::default() });
+        let mut parser = Parser::new(Grammar::from_lark(&fenced).unwrap()).unwrap();
+
+        assert!(parser.feed(b"Here (all of them):\n```sql\nselect name from users;\n```\nDone."));
+
+        let tree = parser.tree().unwrap();
+
+        assert_eq!(normalize(&tree, &checker.resolve(&tree).unwrap()), ["SELECT users.name FROM users"]);
+    }
+
+    #[test]
+    fn scripts() {
+        let checker = checker(None);
+        let grammar = crate::sql::grammar(&crate::sql::Options {
+            statements: crate::sql::Statement::ALL.to_vec(),
+            max_statements: 3,
+            ..Default::default()
+        });
+        let script = |input: &str| {
+            let mut parser = Parser::new(Grammar::from_lark(&grammar).unwrap()).unwrap();
+
+            assert!(parser.feed(input.as_bytes()), "{}", input);
+
+            parser.tree().unwrap()
+        };
+
+        let tree = script("with t as (select id from users) select id from t; delete from orders; select name from users;");
+        let resolved = checker.resolve(&tree).unwrap();
+        let columns: Vec<Option<usize>> = resolved.columns.iter().map(|columns| columns.as_ref().map(Vec::len)).collect();
+
+        assert_eq!(
+            normalize(&tree, &resolved),
+            ["WITH t AS (SELECT users.id FROM users) SELECT t.id FROM t", "DELETE FROM orders", "SELECT users.name FROM users"],
+        );
+        assert_eq!(columns, [Some(1), None, Some(1)]);
+
+        // CTEs and aliases end with their statement
+        let tree = script("with t as (select id from users) select id from t; select id from t");
+
+        assert_eq!(checker.check(&tree), Err("Unknown table `t`".to_string()));
+
+        let tree = script("select u.id from users u; select u.name from users");
+
+        assert_eq!(checker.check(&tree), Err("Unknown table `u`".to_string()));
+    }
+
+    #[test]
+    fn subquery_depth() {
+        let checker = checker(Some(1));
+        let nested = "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders)";
+        let deeper = "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE id IN (SELECT id FROM users))";
+
+        assert_eq!(check(&checker, nested), Ok(()));
+        assert_eq!(check(&checker, deeper), Err("Subqueries nest deeper than the limit of 1".to_string()));
+        assert_eq!(check(&self::checker(Some(0)), "SELECT * FROM (SELECT * FROM users)").map_err(|_| ()), Err(()));
+        assert_eq!(check(&self::checker(Some(2)), deeper), Ok(()));
+    }
+
+    #[test]
+    fn partial_statements() {
+        let checker = checker(Some(1));
+
+        for input in [
+            "SELECT nope, ",
+            "SELECT nope FROM users ",
+            "SELECT (SELECT nope FROM orders), ",
+            "SELECT name FROM users u JOIN orders o ON o.id = u.id ",
+            "SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE id IN (",
+        ] {
+            assert_eq!(check_partial(&checker, input), Ok(()), "{}", input);
+        }
+
+        for (input, error) in [
+            ("SELECT name FROM nope ", "Unknown table `nope`"),
+            ("SELECT nope FROM users WHERE ", "Unknown column `nope`"),
+            ("SELECT name FROM users WHERE nope = ", "Unknown column `nope`"),
+            ("SELECT name FROM users u WHERE x.n", "Unknown table `x`"),
+            ("SELECT name FROM users u JOIN orders o ON o.nope ", "Unknown column `o.nope`"),
+            ("SELECT name FROM users WHERE id IN (SELECT user_id FROM orders WHERE id IN (SELECT ", "Subqueries nest deeper than the limit of 1"),
+        ] {
+            assert_eq!(check_partial(&checker, input), Err(error.to_string()), "{}", input);
+        }
+    }
+}
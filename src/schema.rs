@@ -0,0 +1,1063 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+const JSON: &str = r#"
+json_value: json_object | json_array | JSON_STRING | JSON_NUMBER | "true" | "false" | "null"
+json_object: "{" [JSON_STRING ":" json_value ("," JSON_STRING ":" json_value)*] "}"
+json_array: "[" [json_value ("," json_value)*] "]"
+JSON_STRING: "\"" JSON_CHAR* "\""
+JSON_CHAR: /[^"\\\x00-\x1f]|\\["\\\/bfnrt]|\\u[0-9a-fA-F]{4}/
+JSON_NUMBER: /-?(?:0|[1-9][0-9]*)(?:\.[0-9]+)?(?:[eE][+-]?[0-9]+)?/
+%ignore /[ \t\n\r]+/
+"#;
+
+const CHAR: &str = r#"(?:[^"\\\x00-\x1f]|\\["\\/bfnrt]|\\u[0-9a-fA-F]{4})"#;
+
+/// Characters that appear unescaped inside a JSON string.
+const UNESCAPED: &str = r#"[^"\\\x00-\x1f]"#;
+
+/// Arrays are unrolled up to `maxItems`, so keep the grammar from exploding.
+const MAX_UNROLLED_ITEMS: u64 = 64;
+
+/// Keywords that choose the value instead of constraining a type.
+const COMBINATORS: &[&str] = &["$ref", "const", "enum", "anyOf", "oneOf"];
+
+/// Keywords that only describe the schema.
+const ANNOTATIONS: &[&str] = &[
+    "$schema", "$id", "$comment", "$defs", "definitions", "title", "description", "default", "examples",
+    "deprecated", "readOnly", "writeOnly",
+];
+
+/// Keywords that constrain values of the listed types.
+const TYPE_KEYWORDS: &[(&str, &[&str])] = &[
+    ("properties", &["object"]),
+    ("required", &["object"]),
+    ("additionalProperties", &["object"]),
+    ("items", &["array"]),
+    ("minItems", &["array"]),
+    ("maxItems", &["array"]),
+    ("pattern", &["string"]),
+    ("minLength", &["string"]),
+    ("maxLength", &["string"]),
+    ("minimum", &["number", "integer"]),
+    ("maximum", &["number", "integer"]),
+    ("exclusiveMinimum", &["number", "integer"]),
+    ("exclusiveMaximum", &["number", "integer"]),
+];
+
+fn literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn regex(pattern: &str) -> String {
+    let mut escaped = String::from("/");
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                escaped.push('\\');
+                escaped.extend(chars.next());
+            }
+            '/' => escaped.push_str("\\/"),
+            '\n' => escaped.push_str("\\n"),
+            other => escaped.push(other),
+        }
+    }
+
+    escaped.push('/');
+    escaped
+}
+
+/// Rewrites a `pattern` over string contents into a regex over the JSON
+/// encoding of those contents. Characters JSON escapes are only matched
+/// where the pattern spells them out (`"`, `\\`, `\n`, `\r`, `\t`); `.`,
+/// classes and escapes such as `\S` are narrowed to the other characters.
+fn quote_pattern(pattern: &str) -> Result<String, String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut quoted = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '.' => quoted.push_str(UNESCAPED),
+            '"' => quoted.push_str(r#"\\""#),
+            '\n' => quoted.push_str(r"\\n"),
+            '\r' => quoted.push_str(r"\\r"),
+            '\t' => quoted.push_str(r"\\t"),
+            c if c < ' ' => return Err("`pattern` contains a control character".to_string()),
+            '[' => {
+                let start = i;
+                let mut depth = 0;
+
+                loop {
+                    match chars.get(i) {
+                        None => return Err("Unterminated character class in `pattern`".to_string()),
+                        Some('\\') => i += 2,
+                        Some('[') => {
+                            depth += 1;
+                            i += 1;
+
+                            // `]` right after the opening bracket is literal
+                            if chars.get(i) == Some(&'^') {
+                                i += 1;
+                            }
+
+                            if chars.get(i) == Some(&']') {
+                                i += 1;
+                            }
+                        }
+                        Some(']') if depth == 1 => break,
+                        Some(']') => {
+                            depth -= 1;
+                            i += 1;
+                        }
+                        Some(_) => i += 1,
+                    }
+                }
+
+                let class: String = chars[start..=i].iter().collect();
+
+                quoted.push_str(&format!("[{}&&{}]", class, UNESCAPED));
+            }
+            '\\' => {
+                let Some(&c) = chars.get(i + 1) else {
+                    return Err("`pattern` ends with `\\`".to_string());
+                };
+
+                i += 1;
+
+                match c {
+                    '\\' => quoted.push_str(r"\\\\"),
+                    '"' => quoted.push_str(r#"\\""#),
+                    '/' => quoted.push('/'),
+                    'n' => quoted.push_str(r"\\n"),
+                    'r' => quoted.push_str(r"\\r"),
+                    't' => quoted.push_str(r"\\t"),
+                    'd' | 'D' | 'w' | 'W' | 's' | 'S' | 'p' | 'P' | 'x' | 'u' | 'U' => {
+                        let start = i - 1;
+                        let width = match c {
+                            'x' => 2,
+                            'u' => 4,
+                            'U' => 8,
+                            'p' | 'P' => 1,
+                            _ => 0,
+                        };
+
+                        if width > 0 && chars.get(i + 1) == Some(&'{') {
+                            while chars.get(i).is_some_and(|c| *c != '}') {
+                                i += 1;
+                            }
+                        } else {
+                            i += width;
+                        }
+
+                        let Some(escape) = chars.get(start..=i) else {
+                            return Err("Incomplete escape in `pattern`".to_string());
+                        };
+
+                        let escape: String = escape.iter().collect();
+
+                        quoted.push_str(&format!("[{}&&{}]", escape, UNESCAPED));
+                    }
+                    other => {
+                        quoted.push('\\');
+                        quoted.push(other);
+                    }
+                }
+            }
+            other => quoted.push(other),
+        }
+
+        i += 1;
+    }
+
+    Ok(quoted)
+}
+
+fn digits(n: u128) -> u32 {
+    n.checked_ilog10().unwrap_or(0) + 1
+}
+
+/// Regex for the decimal representations of the integers in `lo..=hi`
+/// (unbounded above when `hi` is `None`). Bounds are at most `u64::MAX`, so
+/// none of the arithmetic below can overflow a `u128`.
+pub fn range(lo: u128, hi: Option<u128>) -> String {
+    let mut alternatives = Vec::new();
+    let mut start = lo;
+
+    loop {
+        let width = digits(start);
+        let top = 10u128.pow(width - 1);
+        let last = 10u128.pow(width) - 1;
+        let end = hi.map_or(last, |hi| hi.min(last));
+
+        while start <= end {
+            let mut step = 1;
+
+            while step < top && start.is_multiple_of(step * 10) && start + step * 10 - 1 <= end {
+                step *= 10;
+            }
+
+            let digit = (start / step) % 10;
+            let count = (10 - digit).min((end - start + 1) / step);
+            let zeros = step.ilog10() as usize;
+            let prefix = (start / step / 10).to_string();
+            let prefix = if start / step / 10 == 0 { "" } else { prefix.as_str() };
+
+            let mut alternative = format!("{}[{}-{}]", prefix, digit, digit + count - 1);
+
+            if zeros > 0 {
+                alternative.push_str(&format!("[0-9]{{{}}}", zeros));
+            }
+
+            alternatives.push(alternative);
+            start += count * step;
+        }
+
+        match hi {
+            Some(hi) if end >= hi => break,
+            None => {
+                alternatives.push(format!("[1-9][0-9]{{{},}}", width));
+
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    format!("(?:{})", alternatives.join("|"))
+}
+
+#[derive(Clone, Copy)]
+struct Bound {
+    value: u128,
+    exclusive: bool,
+}
+
+/// Regex for non-negative decimals between `lo` and `hi`, with an optional
+/// fractional part when `fraction` is set.
+fn magnitude(lo: Bound, hi: Option<Bound>, fraction: bool) -> Option<String> {
+    let any = r"(?:\.[0-9]+)?";
+    let zero = r"(?:\.0+)?";
+    let positive = r"\.[0-9]*[1-9][0-9]*";
+
+    if !fraction {
+        let lo = lo.value + lo.exclusive as u128;
+        let hi = match hi {
+            Some(Bound { value: 0, exclusive: true }) => return None,
+            Some(hi) => Some(hi.value - hi.exclusive as u128),
+            None => None,
+        };
+
+        return match hi {
+            Some(hi) if hi < lo => None,
+            _ => Some(range(lo, hi)),
+        };
+    }
+
+    if let Some(hi) = hi {
+        if hi.value < lo.value || (hi.value == lo.value && (hi.exclusive || lo.exclusive)) {
+            return None;
+        }
+    }
+
+    let mut alternatives = Vec::new();
+    let first = lo.value + lo.exclusive as u128;
+
+    match hi {
+        Some(hi) if hi.value > first => alternatives.push(format!("{}{}", range(first, Some(hi.value - 1)), any)),
+        None => alternatives.push(format!("{}{}", range(first, None), any)),
+        _ => {}
+    }
+
+    if lo.exclusive {
+        alternatives.push(format!("{}{}", lo.value, positive));
+    }
+
+    if let Some(hi) = hi.filter(|hi| !hi.exclusive && hi.value >= first) {
+        alternatives.push(format!("{}{}", hi.value, zero));
+    }
+
+    Some(format!("(?:{})", alternatives.join("|")))
+}
+
+fn bound(schema: &Value, inclusive: &str, exclusive: &str, integer: bool, upper: bool) -> Result<Option<(i128, bool)>, String> {
+    let mut result = None;
+
+    // Draft 4 spells exclusive bounds as a flag next to the inclusive one
+    let flag = schema.get(exclusive).and_then(Value::as_bool);
+
+    for (key, strict) in [(inclusive, flag == Some(true)), (exclusive, true)] {
+        let Some(value) = schema.get(key).filter(|_| flag.is_none() || key == inclusive) else { continue };
+        let mut strict = strict;
+
+        let value = if let Some(value) = value.as_u64() {
+            value as i128
+        } else if let Some(value) = value.as_i64() {
+            value as i128
+        } else if let Some(value) = value.as_f64() {
+            // Larger magnitudes can't be written as `u64` digits
+            if value.abs() >= u64::MAX as f64 {
+                return Err(format!("`{}` is out of range", key));
+            }
+
+            if value.fract() != 0.0 {
+                if !integer {
+                    return Err(format!("Non-integer `{}` is not supported", key));
+                }
+
+                strict = false;
+            }
+
+            (if upper { value.floor() } else { value.ceil() }) as i128
+        } else {
+            return Err(format!("`{}` must be a number", key));
+        };
+
+        let tighter = match result {
+            None => true,
+            Some((current, _)) if upper => value < current || (value == current && strict),
+            Some((current, _)) => value > current || (value == current && strict),
+        };
+
+        if tighter {
+            result = Some((value, strict));
+        }
+    }
+
+    Ok(result)
+}
+
+fn number(schema: &Value, integer: bool) -> Result<String, String> {
+    let lo = bound(schema, "minimum", "exclusiveMinimum", integer, false)?;
+    let hi = bound(schema, "maximum", "exclusiveMaximum", integer, true)?;
+
+    if lo.is_none() && hi.is_none() {
+        return Ok(if integer {
+            regex("-?(?:0|[1-9][0-9]*)")
+        } else {
+            "JSON_NUMBER".to_string()
+        });
+    }
+
+    let to_bound = |(value, exclusive): (i128, bool)| Bound { value: value.unsigned_abs(), exclusive };
+
+    // Non-negative values: 0 (or the lower bound) up to the upper bound
+    let positive = match (lo, hi) {
+        (_, Some((hi, strict))) if hi < 0 || (hi == 0 && strict) => None,
+        (Some(lo), hi) if lo.0 >= 0 => magnitude(to_bound(lo), hi.map(to_bound), !integer),
+        (_, hi) => magnitude(Bound { value: 0, exclusive: false }, hi.map(to_bound), !integer),
+    };
+
+    // Negative values mirror the bounds around zero, excluding zero itself
+    let negative = match lo {
+        Some((lo, _)) if lo >= 0 => None,
+        _ => {
+            let min = match hi {
+                Some((hi, strict)) if hi <= 0 => Bound { value: hi.unsigned_abs(), exclusive: strict || hi == 0 },
+                _ => Bound { value: 0, exclusive: true },
+            };
+
+            magnitude(min, lo.map(to_bound), !integer)
+        }
+    };
+
+    let alternatives: Vec<String> = positive
+        .into_iter()
+        .chain(negative.map(|negative| format!("-{}", negative)))
+        .collect();
+
+    if alternatives.is_empty() {
+        return Err("Numeric bounds leave no valid values".to_string());
+    }
+
+    Ok(regex(&alternatives.join("|")))
+}
+
+/// Splits `pattern` at its top-level `|`s into alternatives, each with
+/// whether it is anchored at the start and at the end. Anchors anywhere else
+/// are not supported.
+fn anchored_alternatives(pattern: &str) -> Result<Vec<(String, bool, bool)>, String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut alternatives = Vec::new();
+    let mut anchors = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut class = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => {
+                class += 1;
+
+                // `]` right after the opening bracket is literal
+                if chars.get(i + 1) == Some(&'^') {
+                    i += 1;
+                }
+
+                if chars.get(i + 1) == Some(&']') {
+                    i += 1;
+                }
+            }
+            ']' if class > 0 => class -= 1,
+            _ if class > 0 => {}
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '^' | '$' => anchors.push(i),
+            '|' if depth == 0 => {
+                alternatives.push((start, i));
+                start = i + 1;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    alternatives.push((start, chars.len()));
+
+    alternatives
+        .into_iter()
+        .map(|(start, end)| {
+            let head = end > start && chars[start] == '^' && anchors.contains(&start);
+            let tail = end > start + head as usize && chars[end - 1] == '$' && anchors.contains(&(end - 1));
+            let (start, end) = (start + head as usize, end - tail as usize);
+
+            if anchors.iter().any(|&anchor| anchor >= start && anchor < end) {
+                return Err("`^` and `$` are only supported at the ends of `pattern` alternatives".to_string());
+            }
+
+            Ok((chars[start..end].iter().collect(), head, tail))
+        })
+        .collect()
+}
+
+fn string(schema: &Value) -> Result<String, String> {
+    let min = schema.get("minLength").and_then(Value::as_u64);
+    let max = schema.get("maxLength").and_then(Value::as_u64);
+
+    let length = match (min, max) {
+        (None, None) => None,
+        (min, Some(max)) => Some(regex(&format!("\"{}{{{},{}}}\"", CHAR, min.unwrap_or(0), max))),
+        (min, None) => Some(regex(&format!("\"{}{{{},}}\"", CHAR, min.unwrap_or(0)))),
+    };
+
+    let Some(pattern) = schema.get("pattern") else {
+        return Ok(length.unwrap_or_else(|| "JSON_STRING".to_string()));
+    };
+
+    let Some(pattern) = pattern.as_str() else {
+        return Err("`pattern` must be a string".to_string());
+    };
+
+    // Unanchored alternatives may match anywhere inside the string
+    let any = format!("{}*", CHAR);
+    let alternatives = anchored_alternatives(pattern)?
+        .into_iter()
+        .map(|(alternative, head, tail)| {
+            let prefix = if head { "" } else { any.as_str() };
+            let suffix = if tail { "" } else { any.as_str() };
+
+            Ok(format!("{}(?:{}){}", prefix, quote_pattern(&alternative)?, suffix))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let pattern = regex(&format!("\"(?:{})\"", alternatives.join("|")));
+
+    // Both have to hold, so intersect the two terminals
+    Ok(match length {
+        Some(length) => format!("({} & {})", pattern, length),
+        None => pattern,
+    })
+}
+
+/// Whether `value` is an instance of the JSON Schema `type` `kind`.
+fn has_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    }
+}
+
+/// Rejects keywords the compiler would otherwise ignore, since ignoring a
+/// constraint lets through values the schema forbids.
+fn check_keywords(schema: &Value) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+
+    let kinds: Vec<&str> = match schema.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+
+    let mut combinators = COMBINATORS.iter().filter(|key| schema.contains_key(**key));
+    let combinator = combinators.next();
+
+    if let (Some(first), Some(second)) = (combinator, combinators.next()) {
+        return Err(format!("`{}` cannot be combined with `{}`", first, second));
+    }
+
+    for key in schema.keys().map(String::as_str) {
+        if ANNOTATIONS.contains(&key) || COMBINATORS.contains(&key) {
+            continue;
+        }
+
+        if key == "type" {
+            match combinator {
+                Some(&combinator) if !["const", "enum"].contains(&combinator) => {
+                    return Err(format!("`type` cannot be combined with `{}`", combinator));
+                }
+                _ => continue,
+            }
+        }
+
+        let Some((_, types)) = TYPE_KEYWORDS.iter().find(|(keyword, _)| *keyword == key) else {
+            return Err(format!("`{}` is not supported", key));
+        };
+
+        if let Some(combinator) = combinator {
+            return Err(format!("`{}` cannot be combined with `{}`", key, combinator));
+        }
+
+        if !kinds.iter().any(|kind| types.contains(kind)) {
+            return Err(format!("`{}` needs `type` {}", key, types.join(" or ")));
+        }
+    }
+
+    Ok(())
+}
+
+struct Compiler<'a> {
+    root: &'a Value,
+    rules: Vec<String>,
+    refs: HashMap<String, String>,
+}
+
+impl Compiler<'_> {
+    fn rule(&mut self, prefix: &str, body: String) -> String {
+        let name = format!("{}_{}", prefix, self.rules.len());
+
+        self.rules.push(format!("{}: {}", name, body));
+
+        name
+    }
+
+    fn reference(&mut self, reference: &str) -> Result<String, String> {
+        if let Some(name) = self.refs.get(reference) {
+            return Ok(name.clone());
+        }
+
+        let Some(target) = reference.strip_prefix('#').and_then(|pointer| self.root.pointer(pointer)) else {
+            return Err(format!("Unresolvable `$ref` `{}`", reference));
+        };
+
+        // Reserve the name first so recursive schemas refer back to it
+        let name = format!("ref_{}", self.refs.len());
+        let index = self.rules.len();
+
+        self.refs.insert(reference.to_string(), name.clone());
+        self.rules.push(String::new());
+
+        let body = self.value(target)?;
+
+        self.rules[index] = format!("{}: {}", name, body);
+
+        Ok(name)
+    }
+
+    fn alternatives(&mut self, schemas: &[Value]) -> Result<String, String> {
+        let alternatives = schemas
+            .iter()
+            .map(|schema| self.value(schema))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(format!("({})", alternatives.join(" | ")))
+    }
+
+    fn object(&mut self, schema: &Value) -> Result<String, String> {
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+
+        // Only declared properties are ever generated, which also satisfies
+        // any `additionalProperties`
+        let empty = Map::new();
+        let properties = match (schema.get("properties"), schema.get("additionalProperties")) {
+            (Some(properties), _) => properties.as_object().ok_or("`properties` must be an object")?,
+            (None, Some(additional)) if additional != &Value::Bool(true) => &empty,
+            (None, _) if required.is_empty() => return Ok("json_object".to_string()),
+            (None, _) => &empty,
+        };
+
+        if let Some(name) = required.iter().find(|name| !properties.contains_key(**name)) {
+            return Err(format!("Required property `{}` is not in `properties`", name));
+        }
+
+        let mut members = Vec::new();
+
+        for (key, value) in properties {
+            let name = literal(&serde_json::to_string(key).unwrap());
+            let member = format!("{} \":\" {}", name, self.value(value)?);
+
+            members.push((member, required.contains(&key.as_str())));
+        }
+
+        // Properties keep their declared order; `first` is the remainder when
+        // nothing has been written yet, `rest` when a comma is needed first
+        let mut first = String::new();
+        let mut rest = String::new();
+
+        for (member, required) in members.into_iter().rev() {
+            let next_rest = if required {
+                format!("\",\" {} {}", member, rest)
+            } else {
+                format!("[\",\" {}] {}", member, rest)
+            };
+
+            let next_first = match (required, first.is_empty()) {
+                (true, _) => format!("{} {}", member, rest),
+                (false, true) => format!("[{} {}]", member, rest),
+                (false, false) => format!("{} {} | {}", member, rest, first),
+            };
+
+            rest = self.rule("rest", next_rest);
+            first = self.rule("first", next_first);
+        }
+
+        Ok(format!("\"{{\" {} \"}}\"", first))
+    }
+
+    fn array(&mut self, schema: &Value) -> Result<String, String> {
+        let item = match schema.get("items") {
+            Some(Value::Array(_)) => return Err("Tuple `items` are not supported".to_string()),
+            Some(items) => self.value(items)?,
+            None => "json_value".to_string(),
+        };
+
+        let min = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+        let max = schema.get("maxItems").and_then(Value::as_u64);
+
+        if max.is_some_and(|max| max < min) {
+            return Err("`maxItems` is smaller than `minItems`".to_string());
+        }
+
+        if min.max(max.unwrap_or(0)) > MAX_UNROLLED_ITEMS {
+            return Err(format!("Array bounds above {} are not supported", MAX_UNROLLED_ITEMS));
+        }
+
+        let separated = format!("\",\" {}", item);
+        let mut body: Vec<&str> = (0..min).map(|i| if i == 0 { item.as_str() } else { separated.as_str() }).collect();
+
+        let tail = match max {
+            Some(max) => {
+                let mut tail = String::new();
+
+                for i in (min..max).rev() {
+                    let next = if i == 0 { &item } else { &separated };
+
+                    tail = format!("[{} {}]", next, tail);
+                }
+
+                tail
+            }
+            None if min == 0 => format!("[{} ({})*]", item, separated),
+            None => format!("({})*", separated),
+        };
+
+        body.push(&tail);
+
+        Ok(self.rule("array", format!("\"[\" {} \"]\"", body.join(" "))))
+    }
+
+    fn typed(&mut self, schema: &Value, kind: &str) -> Result<String, String> {
+        match kind {
+            "object" => self.object(schema),
+            "array" => self.array(schema),
+            "string" => string(schema),
+            "number" => number(schema, false),
+            "integer" => number(schema, true),
+            "boolean" => Ok("(\"true\" | \"false\")".to_string()),
+            "null" => Ok("\"null\"".to_string()),
+            other => Err(format!("Unknown type `{}`", other)),
+        }
+    }
+
+    fn value(&mut self, schema: &Value) -> Result<String, String> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok("json_value".to_string()),
+            Value::Object(_) => schema,
+            _ => return Err("Schema must be an object or `true`".to_string()),
+        };
+
+        check_keywords(schema)?;
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            return self.reference(reference);
+        }
+
+        // `type` still applies to `const` and `enum` values
+        let kinds: Option<Vec<&str>> = match schema.get("type") {
+            Some(Value::String(kind)) => Some(vec![kind.as_str()]),
+            Some(Value::Array(kinds)) => Some(kinds.iter().filter_map(Value::as_str).collect()),
+            _ => None,
+        };
+
+        let typed = |value: &Value| kinds.as_ref().is_none_or(|kinds| kinds.iter().any(|kind| has_type(value, kind)));
+
+        if let Some(value) = schema.get("const") {
+            if !typed(value) {
+                return Err("`const` does not match `type`".to_string());
+            }
+
+            return Ok(literal(&value.to_string()));
+        }
+
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let values: Vec<String> = values
+                .iter()
+                .filter(|value| typed(value))
+                .map(|value| literal(&value.to_string()))
+                .collect();
+
+            if values.is_empty() {
+                return Err("No `enum` value matches `type`".to_string());
+            }
+
+            return Ok(format!("({})", values.join(" | ")));
+        }
+
+        for key in ["anyOf", "oneOf"] {
+            if let Some(schemas) = schema.get(key).and_then(Value::as_array) {
+                return self.alternatives(schemas);
+            }
+        }
+
+        match schema.get("type") {
+            None => Ok("json_value".to_string()),
+            Some(Value::String(kind)) => self.typed(schema, kind),
+            Some(Value::Array(kinds)) => {
+                let kinds = kinds
+                    .iter()
+                    .map(|kind| match kind.as_str() {
+                        Some(kind) => self.typed(schema, kind),
+                        None => Err("`type` entries must be strings".to_string()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(format!("({})", kinds.join(" | ")))
+            }
+            Some(_) => Err("`type` must be a string or an array".to_string()),
+        }
+    }
+}
+
+/// Translates a JSON Schema into Lark grammar text for `Grammar::from_lark`.
+/// Supports objects (properties in declared order, `required`), arrays,
+/// `enum`/`const`, `anyOf`/`oneOf`, local `$ref`s, strings with `pattern` and
+/// length limits, and numbers with integer bounds. Any other keyword that
+/// constrains values is an error.
+pub fn to_lark(schema: &str) -> Result<String, String> {
+    let root: Value = serde_json::from_str(schema).map_err(|e| e.to_string())?;
+    let mut compiler = Compiler { root: &root, rules: Vec::new(), refs: HashMap::new() };
+    let start = compiler.value(&root)?;
+
+    compiler.rules.push(format!("start: {}", start));
+
+    Ok(format!("{}\n{}", compiler.rules.join("\n"), JSON))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        derivre::Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn ranges_match_brute_force() {
+        let bounds = [0, 1, 7, 9, 10, 11, 19, 99, 100, 101, 123, 990, 999, 1000, 1001, 2345];
+
+        for lo in bounds {
+            for hi in bounds.iter().copied().filter(|&hi| hi >= lo).map(Some).chain([None]) {
+                let mut regex = derivre::Regex::new(&range(lo, hi)).unwrap();
+
+                for n in 0..3000 {
+                    let expected = n >= lo && hi.is_none_or(|hi| n <= hi);
+
+                    assert_eq!(regex.is_match(&n.to_string()), expected, "{} in {}..={:?}", n, lo, hi);
+                }
+
+                assert!(!regex.is_match(&format!("0{}", lo)), "leading zero in {}..={:?}", lo, hi);
+            }
+        }
+    }
+
+    #[test]
+    fn ranges_reach_u64_max() {
+        let max = u64::MAX as u128;
+        let regex = range(max - 5, Some(max));
+
+        assert!(matches(&regex, "18446744073709551610"));
+        assert!(matches(&regex, "18446744073709551615"));
+        assert!(!matches(&regex, "18446744073709551616"));
+
+        let regex = range(max, None);
+
+        assert!(matches(&regex, "18446744073709551616"));
+        assert!(matches(&regex, "99999999999999999999"));
+        assert!(matches(&regex, "100000000000000000000"));
+        assert!(!matches(&regex, "18446744073709551614"));
+    }
+
+    #[test]
+    fn bounds_out_of_range() {
+        assert!(to_lark(r#"{"type": "integer", "maximum": 18446744073709551615}"#).is_ok());
+        assert!(to_lark(r#"{"type": "integer", "minimum": -9223372036854775808}"#).is_ok());
+        assert!(to_lark(r#"{"type": "integer", "exclusiveMinimum": 18446744073709551615}"#).is_ok());
+        assert_eq!(to_lark(r#"{"type": "integer", "maximum": 1e20}"#).unwrap_err(), "`maximum` is out of range");
+        assert_eq!(to_lark(r#"{"type": "number", "minimum": -1e30}"#).unwrap_err(), "`minimum` is out of range");
+    }
+
+    fn quoted_matches(pattern: &str, text: &str) -> bool {
+        matches(&format!("\"(?:{})\"", quote_pattern(pattern).unwrap()), text)
+    }
+
+    #[test]
+    fn patterns_stay_inside_strings() {
+        for pattern in [".*", "[^a]*", r"\S*", r"[\x00-\x7f]*", r"\D*", r"\x22"] {
+            assert!(!quoted_matches(pattern, r#""a"b""#), "{}", pattern);
+            assert!(!quoted_matches(pattern, r#""a\b""#), "{}", pattern);
+            assert!(!quoted_matches(pattern, "\"a\nb\""), "{}", pattern);
+        }
+
+        assert!(quoted_matches(".*", r#""a b""#));
+        assert!(quoted_matches("[]a]+", r#""]a]""#));
+        assert!(quoted_matches(r#"a"b"#, r#""a\"b""#));
+        assert!(quoted_matches(r"a\\b\n", r#""a\\b\n""#));
+        assert!(quoted_matches(r"[[:digit:]]{2}\/\pL", r#""12/x""#));
+        assert!(!quoted_matches(r"[[:digit:]]{2}\/\pL", r#""12/1""#));
+
+        for pattern in [r"^.*$", r"a\nb", r"^[^/]+/\\d$"] {
+            let schema = serde_json::json!({ "type": "string", "pattern": pattern }).to_string();
+
+            assert!(crate::grammar::Grammar::from_lark(&to_lark(&schema).unwrap()).is_ok(), "{}", pattern);
+        }
+
+        assert_eq!(quote_pattern("[a-z").unwrap_err(), "Unterminated character class in `pattern`");
+        assert_eq!(
+            to_lark(r#"{"type": "string", "pattern": "a(^b)"}"#).unwrap_err(),
+            "`^` and `$` are only supported at the ends of `pattern` alternatives",
+        );
+        assert_eq!(quote_pattern("a\\").unwrap_err(), "`pattern` ends with `\\`");
+    }
+
+    #[test]
+    fn patterns_and_lengths() {
+        let both = r#"{"type": "string", "pattern": "^a+$", "maxLength": 2}"#;
+
+        assert!(accepts(both, r#""aa""#));
+        assert!(!accepts(both, r#""aaaa""#));
+        assert!(!accepts(both, r#""ab""#));
+
+        // Anchors only bind the alternative they are in
+        let anchors = r#"{"type": "string", "pattern": "^a|b$"}"#;
+
+        for (json, expected) in [("ax", true), ("xb", true), ("a", true), ("xa", false), ("bx", false)] {
+            assert_eq!(accepts(anchors, &format!("\"{}\"", json)), expected, "{}", json);
+        }
+
+        let grouped = r#"{"type": "string", "pattern": "^(a|b)$", "minLength": 1}"#;
+
+        assert!(accepts(grouped, r#""b""#));
+        assert!(!accepts(grouped, r#""ab""#));
+
+        // An escaped `$` is a plain character
+        let dollar = r#"{"type": "string", "pattern": "^[0-9]+\\$"}"#;
+
+        assert!(accepts(dollar, r#""12$""#));
+        assert!(accepts(dollar, r#""12$ each""#));
+        assert!(!accepts(dollar, r#""12""#));
+    }
+
+    #[test]
+    fn unsupported_keywords() {
+        for (schema, error) in [
+            (r#"{"allOf": [{"type": "string"}]}"#, "`allOf` is not supported"),
+            (r#"{"not": {"type": "string"}}"#, "`not` is not supported"),
+            (r#"{"if": {"type": "string"}, "then": {"maxLength": 2}}"#, "`if` is not supported"),
+            (r#"{"type": "integer", "multipleOf": 3}"#, "`multipleOf` is not supported"),
+            (r#"{"type": "string", "format": "date"}"#, "`format` is not supported"),
+            (r#"{"type": "object", "minProperties": 1}"#, "`minProperties` is not supported"),
+            (r#"{"type": "object", "patternProperties": {}}"#, "`patternProperties` is not supported"),
+            (r#"{"type": "array", "uniqueItems": true}"#, "`uniqueItems` is not supported"),
+            (r#"{"type": "array", "prefixItems": []}"#, "`prefixItems` is not supported"),
+            (r#"{"type": "array", "items": [{"type": "string"}]}"#, "Tuple `items` are not supported"),
+            (r#"{"minimum": 3}"#, "`minimum` needs `type` number or integer"),
+            (r#"{"type": "string", "minimum": 3}"#, "`minimum` needs `type` number or integer"),
+            (r#"{"enum": [1, 2], "maximum": 1}"#, "`maximum` cannot be combined with `enum`"),
+            (r#"{"type": "object", "anyOf": [{}]}"#, "`type` cannot be combined with `anyOf`"),
+            (r#"{"const": 1, "enum": [1]}"#, "`const` cannot be combined with `enum`"),
+            (r#"{"type": "object", "required": ["a"]}"#, "Required property `a` is not in `properties`"),
+            (r#"{"type": "string", "const": 1}"#, "`const` does not match `type`"),
+            (r#"{"type": "integer", "enum": [1.5, "1"]}"#, "No `enum` value matches `type`"),
+        ] {
+            assert_eq!(to_lark(schema).unwrap_err(), error, "{}", schema);
+        }
+
+        for schema in [
+            r#"{"$schema": "http://json-schema.org/draft-07/schema#", "title": "T", "type": ["string", "null"], "maxLength": 3}"#,
+            r#"{"type": "object", "additionalProperties": false}"#,
+            r#"{"type": "integer", "minimum": 1, "exclusiveMinimum": true}"#,
+            r#"{"enum": ["a"], "type": "string", "description": "d"}"#,
+        ] {
+            assert!(to_lark(schema).is_ok(), "{}", schema);
+        }
+    }
+
+    fn accepts(schema: &str, json: &str) -> bool {
+        let grammar = crate::grammar::Grammar::from_lark(&to_lark(schema).unwrap()).unwrap();
+        let mut parser = crate::parser::Parser::new(grammar).unwrap();
+
+        parser.feed(json.as_bytes()) && parser.is_accepting()
+    }
+
+    #[test]
+    fn numbers_match_brute_force() {
+        let candidates: Vec<String> = (-15..=15)
+            .flat_map(|n: i32| {
+                let sign = if n < 0 { "-" } else { "" };
+
+                [n.to_string(), format!("{}.0", n), format!("{}{}.25", sign, n.abs())]
+            })
+            .chain(["-0.5".to_string(), "0.5".to_string()])
+            .collect();
+
+        let limits = [None, Some(-7), Some(-1), Some(0), Some(3), Some(10)];
+
+        for integer in [true, false] {
+            for lo in limits {
+                for hi in limits.into_iter().filter(|hi| lo.is_some() || hi.is_some()) {
+                    for (lo_strict, hi_strict) in [(false, false), (true, false), (false, true), (true, true)] {
+                        let mut schema = serde_json::json!({});
+
+                        if let Some(lo) = lo {
+                            schema[if lo_strict { "exclusiveMinimum" } else { "minimum" }] = lo.into();
+                        }
+
+                        if let Some(hi) = hi {
+                            schema[if hi_strict { "exclusiveMaximum" } else { "maximum" }] = hi.into();
+                        }
+
+                        let Ok(pattern) = number(&schema, integer) else {
+                            // Only empty ranges are errors
+                            assert!(lo.is_some() && hi.is_some(), "{}", schema);
+
+                            continue;
+                        };
+
+                        let mut regex = derivre::Regex::new(pattern.trim_matches('/')).unwrap();
+
+                        for candidate in &candidates {
+                            let value: f64 = candidate.parse().unwrap();
+                            let expected = (!integer || !candidate.contains('.'))
+                                && lo.is_none_or(|lo| if lo_strict { value > lo as f64 } else { value >= lo as f64 })
+                                && hi.is_none_or(|hi| if hi_strict { value < hi as f64 } else { value <= hi as f64 });
+
+                            assert_eq!(regex.is_match(candidate), expected, "{} for {} ({})", candidate, schema, integer);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn required_and_optional_properties() {
+        let names = ["a", "b", "c"];
+
+        for mask in 0..8 {
+            let required: Vec<&str> = (0..3).filter(|i| mask & (1 << i) != 0).map(|i| names[i]).collect();
+            let schema = serde_json::json!({
+                "type": "object",
+                "properties": { "a": { "type": "integer" }, "b": { "type": "string" }, "c": { "type": "boolean" } },
+                "required": required,
+            })
+            .to_string();
+
+            for present in 0..8 {
+                let members: Vec<&str> = [r#""a": 1"#, r#""b": "x""#, r#""c": true"#]
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| present & (1 << i) != 0)
+                    .map(|(_, member)| member)
+                    .collect();
+
+                let json = format!("{{{}}}", members.join(", "));
+                let expected = present & mask == mask;
+
+                assert_eq!(accepts(&schema, &json), expected, "{} for {}", json, schema);
+            }
+
+            // Declared order is kept
+            if mask == 0 {
+                assert!(!accepts(&schema, r#"{"b": "x", "a": 1}"#));
+                assert!(!accepts(&schema, r#"{, "a": 1}"#));
+            }
+        }
+    }
+
+    #[test]
+    fn nested_schemas() {
+        let tree = r##"{
+            "$ref": "#/$defs/node",
+            "$defs": {
+                "node": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "enum": ["x", "y"] },
+                        "children": { "type": "array", "items": { "$ref": "#/$defs/node" }, "maxItems": 2 }
+                    },
+                    "required": ["name"]
+                }
+            }
+        }"##;
+
+        assert!(accepts(tree, r#"{"name": "x", "children": [{"name": "y"}, {"name": "x", "children": []}]}"#));
+        assert!(!accepts(tree, r#"{"name": "z"}"#));
+        assert!(!accepts(tree, r#"{"name": "x", "children": [{"name": "y"}, {"name": "y"}, {"name": "y"}]}"#));
+
+        let list = r#"{"type": "array", "items": {"type": ["string", "null"], "maxLength": 2}, "minItems": 1}"#;
+
+        assert!(accepts(list, r#"["ab", null]"#));
+        assert!(!accepts(list, "[]"));
+        assert!(!accepts(list, r#"["abc"]"#));
+
+        let typed = r#"{"type": ["string", "null"], "enum": ["a", 1, null, true]}"#;
+
+        assert!(accepts(typed, r#""a""#));
+        assert!(accepts(typed, "null"));
+        assert!(!accepts(typed, "1"));
+        assert!(!accepts(typed, "true"));
+
+        let exclusive = r#"{"type": "integer", "minimum": 1, "exclusiveMinimum": true, "maximum": 3}"#;
+
+        assert!(!accepts(exclusive, "1"));
+        assert!(accepts(exclusive, "2"));
+        assert!(accepts(exclusive, "3"));
+    }
+}
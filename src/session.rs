@@ -0,0 +1,806 @@
+// pyo3 0.22 trips these lints in the code generated for `PyResult` methods
+// and in keyword-only constructors
+#![allow(clippy::useless_conversion, clippy::too_many_arguments)]
+
+use numpy::{PyArray1, PyReadwriteArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+use rayon::prelude::*;
+
+use crate::catalog::Affinity;
+use crate::grammar::Grammar;
+use crate::parser::{Node, Parser, State};
+use crate::query::{self, Checker, Resolved, Verdicts};
+use crate::schema;
+use crate::sql::{self, Statement, Whitespace};
+use crate::trie::TokenTrie;
+use crate::{ENGINE, SCHEMA, Vocabulary};
+
+/// Constrained generation state. Once the input is a complete sentence the
+/// EOS token (if one was given) is allowed as well, and `terminators` may be
+/// appended to end the output; after EOS nothing is allowed.
+///
+/// SQL sessions also hold the statement to the schema rules of their
+/// `checker`: a token is only allowed if the lexemes it completes keep the
+/// statement valid, and EOS only once the whole statement is.
+#[pyclass]
+#[derive(Clone)]
+pub struct Session {
+    parser: Parser,
+    history: Vec<(State, Vec<u8>)>,
+    healing: Vec<u8>,
+    eos: Option<u32>,
+    ended: bool,
+    checker: Option<Checker>,
+    policy: Option<sql::Options>,
+}
+
+impl Session {
+    fn new(grammar: Grammar, eos: Option<u32>, terminators: &[String]) -> Result<Self, String> {
+        let parser = Parser::new(grammar.terminated_by(terminators))?;
+
+        Ok(Self { parser, history: Vec::new(), healing: Vec::new(), eos, ended: false, checker: None, policy: None })
+    }
+
+    fn allowed(&mut self, trie: &TokenTrie) -> Vec<u32> {
+        if self.ended {
+            return Vec::new();
+        }
+
+        let mut routes = match &self.checker {
+            Some(checker) => {
+                let mut verdicts = Verdicts::new(checker, self.parser.literals(), self.parser.complete());
+
+                self.parser.routes_where(trie, &self.healing, |parser| consistent(parser, checker, &mut verdicts))
+            }
+            None if self.healing.is_empty() => self.parser.routes(trie),
+            None => self.parser.routes_after(trie, &self.healing),
+        };
+
+        if let Some(eos) = self.eos.filter(|_| self.healing.is_empty()) {
+            if self.is_complete() {
+                routes.push(eos);
+            }
+        }
+
+        routes
+    }
+
+    /// Whether the input is a complete sentence (that passes the checker).
+    fn is_complete(&mut self) -> bool {
+        let Some(checker) = &self.checker else {
+            return self.parser.is_accepting();
+        };
+
+        self.parser.tree().is_some_and(|tree| checker.check(&tree).is_ok())
+    }
+
+    /// Feeds the token `token_id`, or ends the session on EOS.
+    fn accept(&mut self, vocab: &Vocabulary, token_id: u32) -> bool {
+        if self.ended {
+            return false;
+        }
+
+        if self.eos == Some(token_id) {
+            if !self.healing.is_empty() || !self.is_complete() {
+                return false;
+            }
+
+            self.history.push((self.parser.state(), Vec::new()));
+            self.ended = true;
+
+            return true;
+        }
+
+        let Some(token) = vocab.id_to_token.get(&token_id) else {
+            return false;
+        };
+
+        self.advance(token)
+    }
+
+    /// Feeds `token`, which has to start with the healed prompt text.
+    fn advance(&mut self, token: &str) -> bool {
+        let Some(rest) = token.as_bytes().strip_prefix(self.healing.as_slice()) else {
+            return false;
+        };
+
+        if let Some(checker) = &self.checker {
+            let mut verdicts = Verdicts::new(checker, self.parser.literals(), self.parser.complete());
+            let consistent = self.parser.lookahead(rest, |parser| consistent(parser, checker, &mut verdicts));
+
+            if consistent != Some(true) {
+                return false;
+            }
+        }
+
+        let state = self.parser.state();
+
+        if !self.parser.feed(rest) {
+            return false;
+        }
+
+        self.history.push((state, std::mem::take(&mut self.healing)));
+
+        true
+    }
+
+    /// Why `token_id` is not allowed now, `None` if it is. Follows the checks
+    /// of `accept()`.
+    fn rejection(&mut self, vocab: &Vocabulary, token_id: u32) -> Option<String> {
+        if self.ended {
+            return Some("The output has ended".to_string());
+        }
+
+        if self.eos == Some(token_id) {
+            if !self.healing.is_empty() {
+                return Some(format!("The prompt text `{}` has to be completed first", String::from_utf8_lossy(&self.healing)));
+            }
+
+            let Some(tree) = self.parser.tree() else {
+                return Some(format!("The input is incomplete: expected {}", listing(&self.parser.expected())));
+            };
+
+            return self.checker.as_ref().and_then(|checker| checker.check(&tree).err());
+        }
+
+        let Some(token) = vocab.id_to_token.get(&token_id) else {
+            return Some(format!("Unknown token {}", token_id));
+        };
+
+        let Some(rest) = token.as_bytes().strip_prefix(self.healing.as_slice()) else {
+            return Some(format!("The token has to start with the prompt text `{}`", String::from_utf8_lossy(&self.healing)));
+        };
+
+        // The longest part of the token the grammar takes
+        let taken = (0..=rest.len())
+            .rev()
+            .find(|&len| self.parser.lookahead(&rest[..len], |_| ()).is_some())
+            .unwrap_or(0);
+
+        if taken < rest.len() {
+            let (pending, expected) = self.parser
+                .lookahead(&rest[..taken], |parser| (parser.pending().to_vec(), parser.expected()))
+                .unwrap_or_default();
+
+            let text = String::from_utf8_lossy(&[pending.as_slice(), &rest[taken..]].concat()).into_owned();
+            let word = stopped_at(&text);
+            let denial = self.policy.as_ref().and_then(|policy| sql::denial(policy, word, &expected));
+
+            return Some(denial.unwrap_or_else(|| format!("`{}` can't come here: expected {}", word, listing(&expected))));
+        }
+
+        let checker = self.checker.as_ref()?;
+
+        self.parser
+            .lookahead(rest, |parser| {
+                let mut error = None;
+
+                for tree in parser.partials() {
+                    match checker.check_partial(&tree) {
+                        Ok(()) => return None,
+                        Err(e) => error = error.or(Some(e)),
+                    }
+                }
+
+                error
+            })
+            .flatten()
+    }
+
+    fn undo(&mut self, n: usize) -> bool {
+        let Some(len) = self.history.len().checked_sub(n) else {
+            return false;
+        };
+
+        if let Some((state, healing)) = self.history.drain(len..).next() {
+            self.parser.restore(state);
+            self.healing = healing;
+
+            // EOS is always the last token
+            self.ended = false;
+        }
+
+        true
+    }
+}
+
+/// Constructors take the termination policy: `eos_token_id` is allowed once
+/// the input is complete, and any of `terminators` may be appended then.
+#[pymethods]
+impl Session {
+    #[staticmethod]
+    #[pyo3(signature = (pattern, *, eos_token_id = None, terminators = Vec::new()))]
+    fn from_regex(py: Python<'_>, pattern: &str, eos_token_id: Option<u32>, terminators: Vec<String>) -> PyResult<Self> {
+        py.allow_threads(|| {
+            Self::new(Grammar::from_regex(pattern), eos_token_id, &terminators)
+                .map_err(|e| PyValueError::new_err(format!("Invalid regex: {}", e)))
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (grammar, *, eos_token_id = None, terminators = Vec::new()))]
+    fn from_grammar(py: Python<'_>, grammar: &str, eos_token_id: Option<u32>, terminators: Vec<String>) -> PyResult<Self> {
+        py.allow_threads(|| {
+            Grammar::from_lark(grammar)
+                .and_then(|grammar| Self::new(grammar, eos_token_id, &terminators))
+                .map_err(|e| PyValueError::new_err(format!("Invalid grammar: {}", e)))
+        })
+    }
+
+    /// SQL session. `whitespace` is `"free"` (optionally in runs of at most
+    /// `max_whitespace`), `"single"` (one space) or `"pretty"` (one space or
+    /// a newline and indentation). `statements` lists the allowed statement
+    /// kinds (`"select"`, `"insert"`, `"update"`, `"delete"`, `"drop"`,
+    /// `"pragma"`) or the presets `"read_only"` and `"all"`; up to
+    /// `max_statements` of them may follow each other, separated by `;`. With
+    /// `max_limit`, every top-level `SELECT` ends with `LIMIT n`, `n` at most
+    /// `max_limit`. `hidden` names tables and `table.column`s of the schema
+    /// passed to `init_schema` that the query may not mention. With a schema,
+    /// tables and columns have to exist and be in scope where they are used,
+    /// functions get arguments of the types they take and aggregate queries
+    /// group by the other columns they use; subqueries nest at most
+    /// `max_depth` levels deep. With a `trigger` such as "```sql\n", the
+    /// output is free text until the first `trigger`, then the statement, a
+    /// closing ``` and free text again.
+    #[staticmethod]
+    #[pyo3(signature = (
+        *,
+        whitespace = "free",
+        max_whitespace = None,
+        statements = vec!["read_only".to_string()],
+        max_statements = 1,
+        max_limit = None,
+        hidden = Vec::new(),
+        max_depth = None,
+        trigger = None,
+        eos_token_id = None,
+        terminators = Vec::new(),
+    ))]
+    fn from_sql(
+        py: Python<'_>,
+        whitespace: &str,
+        max_whitespace: Option<u32>,
+        statements: Vec<String>,
+        max_statements: u32,
+        max_limit: Option<u64>,
+        hidden: Vec<String>,
+        max_depth: Option<u32>,
+        trigger: Option<String>,
+        eos_token_id: Option<u32>,
+        terminators: Vec<String>,
+    ) -> PyResult<Self> {
+        py.allow_threads(|| {
+            if max_statements == 0 {
+                return Err("`max_statements` must be at least 1".to_string());
+            }
+
+            let mut options = sql::Options {
+                whitespace: Whitespace::parse(whitespace, max_whitespace)?,
+                statements: Statement::parse(&statements)?,
+                max_statements,
+                max_limit,
+                trigger,
+                ..sql::Options::default()
+            };
+
+            let mut checker = Checker { catalog: None, max_depth };
+
+            if let Some(catalog) = SCHEMA.get() {
+                let visible = catalog.without(&hidden)?;
+
+                options.hidden = catalog.hidden_names(&visible);
+                options.hidden_columns = hidden.iter().any(|name| name.contains('.'));
+                checker.catalog = Some(visible);
+            } else if !hidden.is_empty() {
+                return Err("`hidden` needs a schema from `init_schema`".to_string());
+            }
+
+            let grammar = Grammar::from_lark(&sql::grammar(&options))?;
+
+            Ok(Self { checker: Some(checker), policy: Some(options), ..Self::new(grammar, eos_token_id, &terminators)? })
+        })
+        .map_err(|e| PyValueError::new_err(format!("Invalid SQL options: {}", e)))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (schema, *, eos_token_id = None, terminators = Vec::new()))]
+    fn from_json_schema(py: Python<'_>, schema: &str, eos_token_id: Option<u32>, terminators: Vec<String>) -> PyResult<Self> {
+        py.allow_threads(|| {
+            schema::to_lark(schema)
+                .and_then(|grammar| Grammar::from_lark(&grammar))
+                .and_then(|grammar| Self::new(grammar, eos_token_id, &terminators))
+                .map_err(|e| PyValueError::new_err(format!("Invalid JSON schema: {}", e)))
+        })
+    }
+
+    fn routes<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyArray1<u32>> {
+        let Some(vocab) = ENGINE.get() else {
+            return PyArray1::from_slice_bound(py, &[]);
+        };
+
+        let routes = py.allow_threads(|| self.allowed(&vocab.trie));
+
+        PyArray1::from_vec_bound(py, routes)
+    }
+
+    /// Removes the last prompt token and makes the first generated token
+    /// start with its text instead, so it can overlap the prompt boundary.
+    /// Returns the shortened prompt.
+    fn heal(&mut self, mut prompt: Vec<u32>) -> Vec<u32> {
+        let Some(vocab) = ENGINE.get() else {
+            return prompt;
+        };
+
+        let Some(token) = prompt.last().and_then(|id| vocab.id_to_token.get(id)) else {
+            return prompt;
+        };
+
+        self.healing = token.as_bytes().to_vec();
+
+        prompt.pop();
+
+        prompt
+    }
+
+    /// Independent copy for another beam or sample.
+    fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    /// Bytes the grammar allows no alternative to, which can be appended
+    /// without sampling.
+    fn forced<'py>(&mut self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        let forced = py.allow_threads(|| self.parser.forced());
+
+        PyBytes::new_bound(py, &forced)
+    }
+
+    /// Where the input stands: `rules` lists the rules still open, outermost
+    /// first, and `expected` the terminals that can come next. SQL sessions
+    /// add the `clause` the input is in and the `tables` its query has named.
+    fn state<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let (tree, expected) = py.allow_threads(|| (self.parser.partial(), self.parser.expected()));
+        let state = PyDict::new_bound(py);
+        let rules: Vec<&str> = query::spine(&tree).into_iter().filter_map(query::rule).collect();
+
+        state.set_item("rules", rules)?;
+        state.set_item("expected", expected)?;
+
+        if self.checker.is_some() {
+            let position = query::position(&tree);
+
+            state.set_item("clause", position.clause)?;
+            state.set_item("tables", position.tables)?;
+        }
+
+        Ok(state)
+    }
+
+    /// Parse tree of the complete lexemes so far: `{"rule": name, "children":
+    /// [...]}` for rules and `{"terminal": name, "text": text}` for lexemes.
+    fn partial_ast<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let tree = py.allow_threads(|| self.parser.partial());
+
+        to_dict(py, &tree, None)
+    }
+
+    /// The finished input, `None` until it is a complete sentence that passes
+    /// the checks: `{"ast": ...}` with the tree as `partial_ast()` gives it.
+    /// SQL sessions resolve each column reference in it to its `table`,
+    /// `column` and `type`, and add the `statements`: each as normalized
+    /// `sql`, with the result `columns` of a query (their `name` and `type`).
+    fn result<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(tree) = py.allow_threads(|| self.parser.tree()) else {
+            return Ok(None);
+        };
+
+        let result = PyDict::new_bound(py);
+
+        let Some(checker) = &self.checker else {
+            result.set_item("ast", to_dict(py, &tree, None)?)?;
+
+            return Ok(Some(result));
+        };
+
+        let Ok(resolved) = checker.resolve(&tree) else {
+            return Ok(None);
+        };
+
+        let statements = PyList::empty_bound(py);
+
+        for (sql, columns) in query::normalize(&tree, &resolved).into_iter().zip(&resolved.columns) {
+            let statement = PyDict::new_bound(py);
+            let names = PyList::empty_bound(py);
+
+            for column in columns.iter().flatten() {
+                let dict = PyDict::new_bound(py);
+
+                dict.set_item("name", &column.name)?;
+                dict.set_item("type", column.affinity.map(Affinity::name))?;
+                names.append(dict)?;
+            }
+
+            statement.set_item("sql", sql)?;
+            statement.set_item("columns", names)?;
+            statements.append(statement)?;
+        }
+
+        result.set_item("ast", to_dict(py, &tree, Some(&resolved))?)?;
+        result.set_item("statements", statements)?;
+
+        Ok(Some(result))
+    }
+
+    /// Why the token `token_id` is not allowed now, `None` if it is: what the
+    /// grammar expected instead, the rule of the SQL options it breaks or the
+    /// problem it makes with the schema.
+    fn explain(&mut self, py: Python<'_>, token_id: u32) -> Option<String> {
+        let vocab = ENGINE.get()?;
+
+        py.allow_threads(|| self.rejection(vocab, token_id))
+    }
+
+    fn is_finished(&mut self, py: Python<'_>) -> bool {
+        py.allow_threads(|| self.is_complete())
+    }
+
+    /// Longest prefix of `draft` the constraint accepts, with the routes at
+    /// each position up to the first rejected token (or past the last one).
+    /// The session itself does not advance.
+    fn verify<'py>(&mut self, py: Python<'py>, draft: Vec<u32>) -> (usize, Vec<Bound<'py, PyArray1<u32>>>) {
+        let Some(vocab) = ENGINE.get() else {
+            return (0, Vec::new());
+        };
+
+        let (accepted, masks) = py.allow_threads(|| {
+            let mut accepted = 0;
+            let mut masks = Vec::with_capacity(draft.len() + 1);
+
+            for &token_id in &draft {
+                masks.push(self.allowed(&vocab.trie));
+
+                if !self.accept(vocab, token_id) {
+                    break;
+                }
+
+                accepted += 1;
+            }
+
+            if accepted == draft.len() {
+                masks.push(self.allowed(&vocab.trie));
+            }
+
+            self.undo(accepted);
+
+            (accepted, masks)
+        });
+
+        let masks = masks
+            .into_iter()
+            .map(|routes| PyArray1::from_vec_bound(py, routes))
+            .collect();
+
+        (accepted, masks)
+    }
+
+    fn feed(&mut self, py: Python<'_>, token_id: u32) -> i32 {
+        let Some(vocab) = ENGINE.get() else {
+            return 1;
+        };
+
+        py.allow_threads(|| {
+            if !self.accept(vocab, token_id) {
+                return 1;
+            }
+
+            0
+        })
+    }
+
+    /// Undoes the last `n` fed tokens.
+    fn rollback(&mut self, n: usize) -> i32 {
+        if !self.undo(n) {
+            return 1;
+        }
+
+        0
+    }
+}
+
+/// Whether the statement `parser` has read is free of problems no
+/// continuation could fix. Only complete lexemes are checked.
+fn consistent(parser: &mut Parser, checker: &Checker, verdicts: &mut Verdicts) -> bool {
+    // The input before was checked already
+    let Some(key) = verdicts.key(parser.complete()) else {
+        return true;
+    };
+
+    verdicts.get(key, || parser.partials().iter().any(|tree| checker.check_partial(tree).is_ok()))
+}
+
+/// `names` for a message: "`a`, `b` or `c`".
+fn listing(names: &[String]) -> String {
+    match names {
+        [] => "nothing".to_string(),
+        [name] => format!("`{}`", name),
+        [names @ .., last] => {
+            let names: Vec<String> = names.iter().map(|name| format!("`{}`", name)).collect();
+
+            format!("{} or `{}`", names.join(", "), last)
+        }
+    }
+}
+
+/// The word at the start of `text` that the grammar stopped at, or its first
+/// character if it doesn't start with one.
+fn stopped_at(text: &str) -> &str {
+    let text = match text.trim_start() {
+        "" => text,
+        trimmed => trimmed,
+    };
+
+    let word = text.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '"')).unwrap_or(text.len());
+    let first = text.chars().next().map_or(0, char::len_utf8);
+
+    &text[..word.max(first)]
+}
+
+fn to_dict<'py>(py: Python<'py>, node: &Node, resolved: Option<&Resolved>) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+
+    match node {
+        Node::Rule(name, children) => {
+            let children = children.iter().map(|child| to_dict(py, child, resolved)).collect::<PyResult<Vec<_>>>()?;
+
+            dict.set_item("rule", name)?;
+            dict.set_item("children", PyList::new_bound(py, children))?;
+
+            if let Some(reference) = resolved.and_then(|resolved| resolved.reference(node)) {
+                dict.set_item("table", Some(&reference.table).filter(|table| !table.is_empty()))?;
+                dict.set_item("column", &reference.column)?;
+                dict.set_item("type", reference.affinity.map(Affinity::name))?;
+            }
+        }
+        Node::Lexeme(terminal, text) => {
+            dict.set_item("terminal", terminal)?;
+            dict.set_item("text", text)?;
+        }
+    }
+
+    Ok(dict)
+}
+
+/// Fills row `i` of the `[N, vocab]` float32 `masks` with the additive mask
+/// of `sessions[i]`: 0 for allowed tokens, -inf for the rest. Sessions are
+/// processed in parallel without the GIL.
+#[pyfunction]
+pub fn fill_masks(py: Python<'_>, mut sessions: Vec<PyRefMut<'_, Session>>, mut masks: PyReadwriteArray2<'_, f32>) -> i32 {
+    let Some(vocab) = ENGINE.get() else {
+        return 1;
+    };
+
+    let (rows, width) = masks.as_array().dim();
+
+    if rows != sessions.len() || width == 0 {
+        return 1;
+    }
+
+    let Ok(masks) = masks.as_slice_mut() else {
+        return 1;
+    };
+
+    let mut sessions: Vec<&mut Session> = sessions.iter_mut().map(|session| &mut **session).collect();
+
+    py.allow_threads(|| {
+        sessions
+            .par_iter_mut()
+            .zip(masks.par_chunks_mut(width))
+            .for_each(|(session, mask)| {
+                mask.fill(f32::NEG_INFINITY);
+
+                for id in session.allowed(&vocab.trie) {
+                    if let Some(value) = mask.get_mut(id as usize) {
+                        *value = 0.0;
+                    }
+                }
+            });
+    });
+
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOCABULARY: &[&str] = &["name", "na", "me", ",", "x", ";"];
+    const EOS: u32 = 99;
+
+    fn vocabulary() -> Vocabulary {
+        let mut vocabulary = Vocabulary::new();
+
+        for (id, token) in VOCABULARY.iter().enumerate() {
+            vocabulary.id_to_token.insert(id as u32, token);
+        }
+
+        vocabulary.trie = TokenTrie::new(VOCABULARY.iter().enumerate().map(|(id, token)| (token.as_bytes(), id as u32)));
+        vocabulary
+    }
+
+    fn session(terminators: &[&str]) -> Session {
+        let grammar = Grammar::from_lark("start: NAME \",\" NAME\nNAME: /[a-z]+/").unwrap();
+        let terminators: Vec<String> = terminators.iter().map(|t| t.to_string()).collect();
+
+        Session::new(grammar, Some(EOS), &terminators).unwrap()
+    }
+
+    fn sorted(mut routes: Vec<u32>) -> Vec<u32> {
+        routes.sort();
+
+        routes
+    }
+
+    #[test]
+    fn undo_restores_state() {
+        let trie = vocabulary().trie;
+        let mut session = session(&[]);
+
+        assert!(session.advance("na"));
+
+        let routes = sorted(session.allowed(&trie));
+
+        assert!(session.advance("me"));
+        assert!(session.advance(","));
+        assert!(!session.advance(","));
+        assert_eq!(session.history.len(), 3);
+        assert!(session.undo(2));
+        assert_eq!(sorted(session.allowed(&trie)), routes);
+
+        // Undoing more than was fed leaves the session alone
+        assert!(!session.undo(2));
+        assert!(session.advance("me,x"));
+        assert!(session.parser.is_accepting());
+    }
+
+    #[test]
+    fn undo_restores_healing() {
+        let trie = vocabulary().trie;
+        let mut session = session(&[]);
+
+        session.healing = b"na".to_vec();
+
+        let routes = sorted(session.allowed(&trie));
+
+        assert_eq!(routes, vec![0, 1]);
+
+        // The first token has to start with the healed text
+        assert!(!session.advance("me"));
+        assert!(session.advance("name"));
+        assert!(session.healing.is_empty());
+        assert!(session.advance(","));
+
+        // Rolling back past the healed token brings the prefix back
+        assert!(session.undo(2));
+        assert_eq!(session.healing, b"na");
+        assert_eq!(sorted(session.allowed(&trie)), routes);
+        assert!(session.advance("na"));
+        assert!(session.advance("me,x"));
+        assert!(session.parser.is_accepting());
+    }
+
+    #[test]
+    fn termination_policy() {
+        let vocabulary = vocabulary();
+        let mut session = session(&[";"]);
+
+        assert!(session.advance("name,"));
+        assert!(!session.allowed(&vocabulary.trie).contains(&EOS));
+        assert!(!session.accept(&vocabulary, EOS));
+        assert!(session.accept(&vocabulary, 4));
+
+        // Complete: more letters, the terminator or EOS
+        assert_eq!(sorted(session.allowed(&vocabulary.trie)), vec![0, 1, 2, 4, 5, EOS]);
+
+        // Nothing but EOS after the terminator, and nothing after EOS
+        assert!(session.accept(&vocabulary, 5));
+        assert_eq!(session.allowed(&vocabulary.trie), vec![EOS]);
+        assert!(session.accept(&vocabulary, EOS));
+        assert!(session.allowed(&vocabulary.trie).is_empty());
+        assert!(!session.accept(&vocabulary, EOS));
+
+        assert!(session.undo(2));
+        assert!(session.accept(&vocabulary, EOS));
+    }
+
+    #[test]
+    fn schema_checks() {
+        const SQL: &[&str] = &["SELECT", " name", " nope", " FROM", " users", " WHERE", " =", " 1"];
+
+        let mut vocabulary = Vocabulary::new();
+
+        for (id, token) in SQL.iter().enumerate() {
+            vocabulary.id_to_token.insert(id as u32, token);
+        }
+
+        vocabulary.trie = TokenTrie::new(SQL.iter().enumerate().map(|(id, token)| (token.as_bytes(), id as u32)));
+
+        let grammar = Grammar::from_lark(&sql::grammar(&Default::default())).unwrap();
+        let catalog = crate::catalog::Catalog::from_ddl("CREATE TABLE users (id, name);").unwrap();
+        let mut session = Session::new(grammar, Some(EOS), &[]).unwrap();
+
+        session.checker = Some(Checker { catalog: Some(catalog), max_depth: None });
+
+        // The select list can only be checked once `FROM` is done
+        for id in [0, 2, 3, 4] {
+            assert!(session.allowed(&vocabulary.trie).contains(&id));
+            assert!(session.accept(&vocabulary, id));
+        }
+
+        assert!(!session.allowed(&vocabulary.trie).contains(&EOS));
+        assert!(!session.accept(&vocabulary, EOS));
+        assert!(session.undo(3));
+
+        for id in [1, 3, 4] {
+            assert!(session.accept(&vocabulary, id));
+        }
+
+        assert!(session.allowed(&vocabulary.trie).contains(&EOS));
+        assert!(session.accept(&vocabulary, 5));
+
+        // `nope` could still go on until `=` ends it
+        assert!(session.allowed(&vocabulary.trie).contains(&2));
+        assert!(session.accept(&vocabulary, 2));
+        assert!(!session.allowed(&vocabulary.trie).contains(&6));
+        assert!(!session.accept(&vocabulary, 6));
+    }
+
+    #[test]
+    fn explanations() {
+        const SQL: &[&str] = &["SELECT", "DROP", " name", " email", " emial", " FROM", " users", " LIMIT", " 500", " 5", " LOWER(id)"];
+
+        let mut vocabulary = Vocabulary::new();
+
+        for (id, token) in SQL.iter().enumerate() {
+            vocabulary.id_to_token.insert(id as u32, token);
+        }
+
+        let catalog = crate::catalog::Catalog::from_ddl("CREATE TABLE users (id INTEGER, name TEXT, email TEXT);").unwrap();
+        let visible = catalog.without(&["users.email".to_string()]).unwrap();
+
+        let options = sql::Options {
+            max_limit: Some(100),
+            hidden: catalog.hidden_names(&visible),
+            hidden_columns: true,
+            ..sql::Options::default()
+        };
+
+        let grammar = Grammar::from_lark(&sql::grammar(&options)).unwrap();
+        let mut session = Session::new(grammar, Some(EOS), &[]).unwrap();
+
+        session.checker = Some(Checker { catalog: Some(visible), max_depth: None });
+        session.policy = Some(options);
+
+        let explain = |session: &mut Session, id| session.rejection(&vocabulary, id);
+
+        assert_eq!(explain(&mut session, 0), None);
+        assert_eq!(explain(&mut session, 1), Some("`DROP` statements are not allowed".to_string()));
+        assert_eq!(explain(&mut session, 5), Some("`FROM` can't come here: expected `WITH` or `SELECT`".to_string()));
+        assert_eq!(explain(&mut session, EOS), Some("The input is incomplete: expected `WITH` or `SELECT`".to_string()));
+        assert!(session.advance("SELECT email"));
+        assert_eq!(explain(&mut session, 5), Some("`email` is hidden".to_string()));
+        assert!(session.undo(1));
+        assert!(session.advance("SELECT emial FROM users LIMIT"));
+        assert_eq!(explain(&mut session, 9), Some("Unknown column `emial`".to_string()));
+        assert!(session.undo(1));
+        assert!(session.advance("SELECT name FROM users LIMIT"));
+        assert_eq!(explain(&mut session, 8), Some("`LIMIT` is at most 100".to_string()));
+        assert!(session.undo(1));
+        assert!(session.advance("SELECT LOWER(id) FROM users"));
+        assert_eq!(
+            explain(&mut session, 7),
+            Some("Type mismatch: argument 1 of `lower` has to be text, not INTEGER".to_string()),
+        );
+    }
+}
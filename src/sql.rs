@@ -0,0 +1,421 @@
+//! SQL grammar assembled from `sql.lark` and the options of a session.
+
+use crate::functions::{self, SQLITE};
+use crate::schema;
+
+const RULES: &str = include_str!("sql.lark");
+
+/// What may separate two SQL lexemes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Whitespace {
+    /// Exactly one space.
+    Single,
+    /// Spaces, tabs and newlines, in runs of at most the given length.
+    Free(Option<u32>),
+    /// One space, or a newline followed by indentation in steps of four
+    /// spaces.
+    Pretty,
+}
+
+impl Whitespace {
+    pub fn parse(name: &str, max_run: Option<u32>) -> Result<Self, String> {
+        match (name, max_run) {
+            ("free", Some(0)) => Err("`max_whitespace` must be at least 1".to_string()),
+            ("free", max_run) => Ok(Self::Free(max_run)),
+            (_, Some(_)) => Err("`max_whitespace` only applies to `free` whitespace".to_string()),
+            ("single", None) => Ok(Self::Single),
+            ("pretty", None) => Ok(Self::Pretty),
+            (other, None) => Err(format!("Unknown whitespace policy `{}`", other)),
+        }
+    }
+
+    fn terminal(self) -> String {
+        match self {
+            Self::Single => "\" \"".to_string(),
+            Self::Free(None) => r"/[ \t\n]+/".to_string(),
+            Self::Free(Some(max_run)) => format!(r"/[ \t\n]{{1,{}}}/", max_run),
+            Self::Pretty => r"/ |\n(    )*/".to_string(),
+        }
+    }
+}
+
+/// Kind of SQL statement, by its leading keyword.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Statement {
+    Select,
+    Insert,
+    Update,
+    Delete,
+    Drop,
+    Pragma,
+}
+
+impl Statement {
+    pub const ALL: &[Self] = &[Self::Select, Self::Insert, Self::Update, Self::Delete, Self::Drop, Self::Pragma];
+
+    /// Statements that can't change the database.
+    pub const READ_ONLY: &[Self] = &[Self::Select];
+
+    /// Kinds named in `names`, which may also be the presets `read_only` and
+    /// `all`.
+    pub fn parse(names: &[String]) -> Result<Vec<Self>, String> {
+        let mut kinds = Vec::new();
+
+        for name in names {
+            let named: &[Self] = match name.to_ascii_lowercase().as_str() {
+                "read_only" => Self::READ_ONLY,
+                "all" => Self::ALL,
+                "select" => &[Self::Select],
+                "insert" => &[Self::Insert],
+                "update" => &[Self::Update],
+                "delete" => &[Self::Delete],
+                "drop" => &[Self::Drop],
+                "pragma" => &[Self::Pragma],
+                _ => return Err(format!("Unknown statement kind `{}`", name)),
+            };
+
+            for kind in named {
+                if !kinds.contains(kind) {
+                    kinds.push(*kind);
+                }
+            }
+        }
+
+        if kinds.is_empty() {
+            return Err("No statement kinds are allowed".to_string());
+        }
+
+        Ok(kinds)
+    }
+
+    fn keyword(self) -> &'static str {
+        match self {
+            Self::Select => "SELECT",
+            Self::Insert => "INSERT",
+            Self::Update => "UPDATE",
+            Self::Delete => "DELETE",
+            Self::Drop => "DROP",
+            Self::Pragma => "PRAGMA",
+        }
+    }
+
+    fn rule(self) -> &'static str {
+        match self {
+            Self::Select => "select_stmt",
+            Self::Insert => "insert_stmt",
+            Self::Update => "update_stmt",
+            Self::Delete => "delete_stmt",
+            Self::Drop => "drop_stmt",
+            Self::Pragma => "pragma_stmt",
+        }
+    }
+}
+
+/// A script of up to `max_statements` statements separated by `;`. With
+/// `max_limit`, a top-level `SELECT` has to end with `LIMIT n` for some
+/// `n <= max_limit`. Identifiers in `hidden` never appear in the query, and
+/// neither does `*` while `hidden_columns` is set. With a `trigger`, the
+/// script is fenced: any text up to the first `trigger` comes before it, and
+/// a closing ``` and any text after it.
+#[derive(Clone)]
+pub struct Options {
+    pub whitespace: Whitespace,
+    pub statements: Vec<Statement>,
+    pub max_statements: u32,
+    pub max_limit: Option<u64>,
+    pub hidden: Vec<String>,
+    pub hidden_columns: bool,
+    pub trigger: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            whitespace: Whitespace::Free(None),
+            statements: Statement::READ_ONLY.to_vec(),
+            max_statements: 1,
+            max_limit: None,
+            hidden: Vec::new(),
+            hidden_columns: false,
+            trigger: None,
+        }
+    }
+}
+
+/// `text` escaped for a Lark literal.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t").replace('\r', "\\r")
+}
+
+/// Lark literal matching the identifier `name`, bare or quoted, in any case.
+fn identifier(name: &str) -> String {
+    format!("\"{}\"i | \"\\\"{}\\\"\"i", escape(name), escape(name))
+}
+
+/// Lark grammar text for SQL under `options`.
+pub fn grammar(options: &Options) -> String {
+    let mut grammar = String::from(RULES);
+
+    let statements: Vec<&str> = options.statements
+        .iter()
+        .map(|kind| match (kind, options.max_limit) {
+            (Statement::Select, Some(_)) => "capped_select",
+            (kind, _) => kind.rule(),
+        })
+        .collect();
+
+    // Each further statement is optional
+    let more = options.max_statements.saturating_sub(1) as usize;
+    let script = format!("statement{}{} [\";\"]", " [\";\" statement".repeat(more), "]".repeat(more));
+
+    match &options.trigger {
+        None => grammar.push_str(&format!("\nstart: {}\n", script)),
+        Some(trigger) => {
+            // The text before the fence ends at the first `trigger`
+            grammar.push_str(&format!("\nstart: FENCE_OPEN {} FENCE_CLOSE [AFTER_FENCE]\n", script));
+            grammar.push_str("FENCE_OPEN: (/.*/s TRIGGER) & ~(/.*/s TRIGGER /.+/s)\n");
+            grammar.push_str(&format!("TRIGGER: \"{}\"\n", escape(trigger)));
+            grammar.push_str("FENCE_CLOSE: /\\n?```/\nAFTER_FENCE: /.+/s\n");
+        }
+    }
+
+    grammar.push_str(&format!("statement: {}\n", statements.join(" | ")));
+
+    if let Some(max) = options.max_limit {
+        grammar.push_str("capped_select: select_body \"LIMIT\"i LIMIT_COUNT [\"OFFSET\"i expr]\n");
+        grammar.push_str(&format!("LIMIT_COUNT: /{}/\n", schema::range(0, Some(max as u128))));
+    }
+
+    // `*` would expand to hidden columns too
+    if options.hidden_columns {
+        grammar.push_str("result_column: expr [[\"AS\"i] ALIAS]\n");
+    } else {
+        grammar.push_str("result_column: expr [[\"AS\"i] ALIAS] | \"*\" | QUALIFIER \".\" \"*\"\n");
+    }
+
+    if options.hidden.is_empty() {
+        grammar.push_str("IDENTIFIER: NAME\n");
+    } else {
+        let hidden: Vec<String> = options.hidden.iter().map(|name| identifier(name)).collect();
+
+        grammar.push_str(&format!("IDENTIFIER: NAME & ~HIDDEN\nHIDDEN: {}\n", hidden.join(" | ")));
+    }
+
+    grammar.push_str("TABLE_NAME: IDENTIFIER\nCOLUMN_NAME: IDENTIFIER\nQUALIFIER: IDENTIFIER\nALIAS: IDENTIFIER\n");
+
+    let names: Vec<String> = functions::names(SQLITE).iter().map(|name| format!("\"{}\"i", name)).collect();
+
+    grammar.push_str(&format!("FUNCTION_NAME: {}\n", names.join(" | ")));
+
+    // One ignored lexeme between two others, so the whitespace terminal
+    // alone decides what separates them
+    grammar.push_str(&format!("\n%ignore {}\n%single_ignore\n", options.whitespace.terminal()));
+
+    grammar
+}
+
+/// The rule of `options` that keeps out `word`, where the grammar for them
+/// expected one of `expected` instead, if that is why.
+pub fn denial(options: &Options, word: &str, expected: &[String]) -> Option<String> {
+    let unquoted = word.strip_prefix('"').and_then(|word| word.strip_suffix('"')).unwrap_or(word);
+
+    if options.hidden.iter().any(|name| name.eq_ignore_ascii_case(unquoted)) {
+        return Some(format!("`{}` is hidden", unquoted));
+    }
+
+    if let Some(kind) = Statement::ALL.iter().find(|kind| kind.keyword().eq_ignore_ascii_case(word)) {
+        if !options.statements.contains(kind) {
+            return Some(format!("`{}` statements are not allowed", kind.keyword()));
+        }
+    }
+
+    let max = options.max_limit.filter(|_| expected.iter().any(|name| name == "LIMIT_COUNT"))?;
+
+    word.bytes().all(|byte| byte.is_ascii_digit()).then(|| format!("`LIMIT` is at most {}", max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::Grammar;
+    use crate::parser::Parser;
+
+    fn accepts(options: &Options, query: &str) -> bool {
+        let mut parser = Parser::new(Grammar::from_lark(&grammar(options)).unwrap()).unwrap();
+
+        parser.feed(query.as_bytes()) && parser.is_accepting()
+    }
+
+    #[test]
+    fn whitespace_policies() {
+        let free = Options::default();
+        let limited = Options { whitespace: Whitespace::Free(Some(2)), ..Options::default() };
+        let single = Options { whitespace: Whitespace::Single, ..Options::default() };
+        let pretty = Options { whitespace: Whitespace::Pretty, ..Options::default() };
+
+        for (query, expected) in [
+            ("SELECT name FROM users", [true, true, true, true]),
+            ("SELECT name,\tid FROM users", [true, true, false, false]),
+            ("SELECT  name FROM users", [true, true, false, false]),
+            ("SELECT   name FROM users", [true, false, false, false]),
+            ("SELECT name\nFROM users\nWHERE id = 1", [true, true, false, true]),
+            ("SELECT name\nFROM users\n    WHERE id = 1", [true, false, false, true]),
+            ("SELECT name\n  FROM users", [true, false, false, false]),
+            ("SELECT name \nFROM users", [true, true, false, false]),
+        ] {
+            for (options, expected) in [&free, &limited, &single, &pretty].into_iter().zip(expected) {
+                assert_eq!(accepts(options, query), expected, "{:?} with {:?}", query, options.whitespace);
+            }
+        }
+    }
+
+    #[test]
+    fn statement_allowlist() {
+        let read_only = Options::default();
+        let all = Options { statements: Statement::ALL.to_vec(), ..Options::default() };
+        let writes = Options { statements: vec![Statement::Insert, Statement::Update], ..Options::default() };
+
+        for (query, expected) in [
+            ("WITH t AS (SELECT 1) SELECT * FROM t", [true, true, false]),
+            ("INSERT INTO users (name, email) VALUES ('a', 'b'), ('c', 'd')", [false, true, true]),
+            ("INSERT OR IGNORE INTO archive SELECT * FROM users", [false, true, true]),
+            ("UPDATE users SET name = 'x', email = NULL WHERE id = 1", [false, true, true]),
+            ("DELETE FROM users WHERE id = 1", [false, true, false]),
+            ("DROP TABLE IF EXISTS users", [false, true, false]),
+            ("PRAGMA table_info(users)", [false, true, false]),
+            ("PRAGMA journal_mode = WAL", [false, true, false]),
+        ] {
+            for (options, expected) in [&read_only, &all, &writes].into_iter().zip(expected) {
+                assert_eq!(accepts(options, query), expected, "{:?} with {:?}", query, options.statements);
+            }
+        }
+
+        // Keywords of other statements are still reserved
+        assert!(!accepts(&read_only, "SELECT * FROM users delete"));
+    }
+
+    #[test]
+    fn capped_limit() {
+        let capped = Options { max_limit: Some(100), ..Options::default() };
+
+        for (query, expected) in [
+            ("SELECT * FROM users LIMIT 100", true),
+            ("SELECT * FROM users ORDER BY id LIMIT 0 OFFSET 20", true),
+            ("SELECT * FROM (SELECT * FROM users LIMIT 5000) LIMIT 7;", true),
+            ("SELECT a FROM t UNION SELECT b FROM u LIMIT 10", true),
+            ("SELECT * FROM users", false),
+            ("SELECT * FROM users LIMIT 101", false),
+            ("SELECT * FROM users LIMIT 1000", false),
+            ("SELECT * FROM users LIMIT 010", false),
+            ("SELECT * FROM users LIMIT 1 + 1", false),
+        ] {
+            assert_eq!(accepts(&capped, query), expected, "{}", query);
+        }
+
+        // There is no accepting state before the limit
+        let mut parser = Parser::new(Grammar::from_lark(&grammar(&capped)).unwrap()).unwrap();
+
+        assert!(parser.feed(b"SELECT * FROM users WHERE id > 3"));
+        assert!(!parser.is_accepting());
+        assert!(parser.feed(b" LIMIT 10"));
+        assert!(parser.is_accepting());
+        assert!(!parser.feed(b"00"));
+        assert!(parser.feed(b"0"));
+        assert!(parser.is_accepting());
+    }
+
+    #[test]
+    fn hidden_identifiers() {
+        let hidden = Options {
+            hidden: vec!["email".to_string(), "audit log".to_string()],
+            hidden_columns: true,
+            ..Options::default()
+        };
+
+        for (query, expected) in [
+            ("SELECT name, u.id AS email_id FROM users u", true),
+            ("SELECT COUNT(*) FROM users", true),
+            ("SELECT email FROM users", false),
+            ("SELECT u.EMAIL FROM users u", false),
+            ("SELECT \"Email\" FROM users", false),
+            ("SELECT name AS email FROM users", false),
+            ("SELECT * FROM \"audit log\"", false),
+            ("SELECT * FROM users", false),
+            ("SELECT u.* FROM users u", false),
+        ] {
+            assert_eq!(accepts(&hidden, query), expected, "{}", query);
+        }
+
+        let tables = Options { hidden: vec!["audit".to_string()], ..Options::default() };
+
+        assert!(accepts(&tables, "SELECT u.* FROM users u"));
+        assert!(!accepts(&tables, "SELECT * FROM audit"));
+    }
+
+    #[test]
+    fn fenced_statements() {
+        let fenced = Options { trigger: Some("```sql\n".to_string()), ..Options::default() };
+        let single = Options { whitespace: Whitespace::Single, ..fenced.clone() };
+
+        for (text, expected) in [
+            ("Here you go:\n```sql\nSELECT * FROM users\n```\nIt lists \"users\".", true),
+            ("```sql\nSELECT 1;```", true),
+            ("The `sql` block:\n```sql\nSELECT 1\n```\n```sql\nDROP TABLE users\n```", true),
+            ("Here you go:\n```sql\nSELECT * FROM users", false),
+            ("Here you go: SELECT 1\n```", false),
+            ("```sql\nDROP TABLE users\n```", false),
+            ("```sql\n```sql\nSELECT 1\n```", false),
+        ] {
+            assert_eq!(accepts(&fenced, text), expected, "{}", text);
+        }
+
+        assert!(accepts(&single, "SQL:\n```sql\nSELECT 1\n```"));
+        assert!(!accepts(&single, "SQL:\n```sql\nSELECT  1\n```"));
+    }
+
+    #[test]
+    fn scripts() {
+        let script = Options { max_statements: 2, ..Options::default() };
+
+        for (text, expected) in [
+            ("SELECT 1", true),
+            ("SELECT 1; SELECT * FROM users;", true),
+            ("SELECT 1;;", false),
+            ("SELECT 1; SELECT 2; SELECT 3", false),
+        ] {
+            assert_eq!(accepts(&script, text), expected, "{}", text);
+        }
+
+        assert!(!accepts(&Options::default(), "SELECT 1; SELECT 2"));
+    }
+
+    #[test]
+    fn function_names() {
+        let options = Options::default();
+
+        assert!(accepts(&options, "SELECT lower(name), COUNT(*), \"count\" FROM users"));
+        assert!(accepts(&options, "SELECT count FROM users"));
+        assert!(!accepts(&options, "SELECT nope(name) FROM users"));
+        assert!(!accepts(&options, "SELECT \"lower\"(name) FROM users"));
+    }
+
+    #[test]
+    fn statement_options() {
+        let names = |names: &[&str]| Statement::parse(&names.iter().map(|name| name.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(names(&["read_only"]), Ok(vec![Statement::Select]));
+        assert_eq!(names(&["SELECT", "delete", "select"]), Ok(vec![Statement::Select, Statement::Delete]));
+        assert_eq!(names(&["all"]).unwrap().len(), Statement::ALL.len());
+        assert!(names(&["alter"]).is_err());
+        assert!(names(&[]).is_err());
+    }
+
+    #[test]
+    fn whitespace_options() {
+        assert_eq!(Whitespace::parse("free", Some(3)), Ok(Whitespace::Free(Some(3))));
+        assert_eq!(Whitespace::parse("pretty", None), Ok(Whitespace::Pretty));
+        assert!(Whitespace::parse("single", Some(3)).is_err());
+        assert!(Whitespace::parse("free", Some(0)).is_err());
+        assert!(Whitespace::parse("tabs", None).is_err());
+    }
+}
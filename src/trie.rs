@@ -0,0 +1,110 @@
+struct Node {
+    byte: u8,
+    depth: u32,
+    end: u32,
+    tokens: (u32, u32),
+}
+
+/// Byte-level prefix tree over the vocabulary, stored in pre-order so that a
+/// rejected node skips its whole subtree with a single jump.
+pub struct TokenTrie {
+    nodes: Vec<Node>,
+    token_ids: Vec<u32>,
+}
+
+impl TokenTrie {
+    pub fn new<'a>(tokens: impl IntoIterator<Item = (&'a [u8], u32)>) -> Self {
+        let mut words: Vec<(&[u8], u32)> = tokens
+            .into_iter()
+            .filter(|(word, _)| !word.is_empty())
+            .collect();
+
+        words.sort();
+
+        let mut nodes = vec![Node { byte: 0, depth: 0, end: 0, tokens: (0, 0) }];
+        let mut token_ids = Vec::with_capacity(words.len());
+        let mut path = vec![0];
+        let mut previous: &[u8] = &[];
+
+        for (word, id) in words {
+            let common = word
+                .iter()
+                .zip(previous)
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            while path.len() > common + 1 {
+                let idx = path.pop().unwrap();
+
+                nodes[idx].end = nodes.len() as u32;
+            }
+
+            for &byte in &word[common..] {
+                path.push(nodes.len());
+
+                nodes.push(Node {
+                    byte,
+                    depth: path.len() as u32 - 1,
+                    end: 0,
+                    tokens: (0, 0),
+                });
+            }
+
+            let node = &mut nodes[*path.last().unwrap()];
+
+            if node.tokens.0 == node.tokens.1 {
+                node.tokens = (token_ids.len() as u32, token_ids.len() as u32);
+            }
+
+            node.tokens.1 += 1;
+
+            token_ids.push(id);
+            previous = word;
+        }
+
+        for idx in path {
+            nodes[idx].end = nodes.len() as u32;
+        }
+
+        Self { nodes, token_ids }
+    }
+
+    /// Walks the trie from `initial`, calling `step` for every byte on the way.
+    /// Subtrees where `step` returns `None` are pruned; the ids of all tokens
+    /// whose every byte was accepted are returned.
+    pub fn walk<S: Copy>(&self, initial: S, mut step: impl FnMut(S, u8) -> Option<S>) -> Vec<u32> {
+        self.walk_filtered(initial, |state, byte, _| step(state, byte).map(|state| (state, true)))
+    }
+
+    /// Like `walk`, but `step` also decides whether to keep the tokens that
+    /// end with the byte (its last argument tells if any do); their subtrees
+    /// are still walked either way.
+    pub fn walk_filtered<S: Copy>(&self, initial: S, mut step: impl FnMut(S, u8, bool) -> Option<(S, bool)>) -> Vec<u32> {
+        let mut routes = Vec::new();
+        let mut stack = vec![initial];
+        let mut idx = 1;
+
+        while idx < self.nodes.len() {
+            let node = &self.nodes[idx];
+            let (start, end) = node.tokens;
+
+            stack.truncate(node.depth as usize);
+
+            let Some((state, keep)) = step(*stack.last().unwrap(), node.byte, start < end) else {
+                idx = node.end as usize;
+
+                continue;
+            };
+
+            stack.push(state);
+
+            if keep {
+                routes.extend_from_slice(&self.token_ids[start as usize..end as usize]);
+            }
+
+            idx += 1;
+        }
+
+        routes
+    }
+}